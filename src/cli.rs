@@ -0,0 +1,421 @@
+//! Command line subcommands
+//!
+//! Everything except `sort` runs headless on the sorted folder, no window is opened. Moves go
+//! through the same [Journal] as the window, so they can be undone from either.
+//!
+//! Results go to stdout and diagnostics to stderr. `search` and `stats` only read the folder, the
+//! config and index files are never written by them.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::Subcommand;
+
+use crate::{
+    config::{CollisionPolicy, Config},
+    data_store::DataStore,
+    file_store::{self, Resolution},
+    index::{Entry, LibraryIndex},
+    journal::Journal,
+    metadata::format_size,
+    orientation,
+    query::Query,
+    queue::SortQueue,
+    shortcuts::Bindings,
+};
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Open the sorting window, same as running without a command
+    Sort,
+
+    /// Print the sorted files matching a query, e.g. `anime and evangelion not sticker`
+    Search {
+        /// Query terms, joined with spaces
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+
+    /// Show how many files are sorted, by category and tag
+    Stats,
+
+    /// Undo the most recent moves, including the ones done from the window
+    Undo {
+        /// Number of moves to undo
+        #[arg(short = 'n', long, default_value_t = 1)]
+        count: usize,
+    },
+
+    /// Rescan the sorted folders into the library index
+    Reindex {
+        /// Hash every file again instead of only the changed ones
+        #[arg(long)]
+        full: bool,
+    },
+
+    /// List sorted files with the same contents
+    Dedupe {
+        /// Move every copy but the oldest into this folder
+        #[arg(long)]
+        move_to: Option<PathBuf>,
+    },
+
+    /// Move sorted files to the folder of the first rule they match
+    ApplyRules {
+        /// Only print what would be moved
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check the folder configuration without changing anything
+    ValidateConfig,
+}
+
+/// Config and index of a sorted folder
+struct Library {
+    /// Canonical sorted folder
+    root: PathBuf,
+    config: DataStore<Config>,
+    index: LibraryIndex,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+/// Run `command` on `folder`
+///
+/// [CliCommand::Sort] can't run headless and fails.
+pub fn run(folder: &Path, command: CliCommand) -> ExitCode {
+    let res = match command {
+        CliCommand::Sort => Err("sort opens the window, it can't run headless".to_string()),
+        CliCommand::Search { query } => search(folder, &query.join(" ")),
+        CliCommand::Stats => stats(folder),
+        CliCommand::Undo { count } => undo(folder, count),
+        CliCommand::Reindex { full } => reindex(folder, full),
+        CliCommand::Dedupe { move_to } => dedupe(folder, move_to.as_deref()),
+        CliCommand::ApplyRules { dry_run } => apply_rules(folder, dry_run),
+        CliCommand::ValidateConfig => validate_config(folder),
+    };
+
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+impl Library {
+    /// Load the folder config and index, rescanning the sorted folders and saving the index if
+    /// `scan` is set
+    fn open(folder: &Path, scan: bool) -> Result<Self, String> {
+        let config = Config::load(folder).map_err(|e| e.to_string())?;
+        let mut library = Self::with_config(folder, config)?;
+
+        if scan {
+            library.scan()?;
+            library.save_index();
+        }
+
+        Ok(library)
+    }
+
+    /// Load the folder config and a rescanned index without writing anything
+    fn read(folder: &Path) -> Result<Self, String> {
+        let config = Config::read(folder).map_err(|e| e.to_string())?;
+        let mut library = Self::with_config(folder, config)?;
+
+        library.scan()?;
+        Ok(library)
+    }
+
+    fn with_config(folder: &Path, config: DataStore<Config>) -> Result<Self, String> {
+        let root = folder.canonicalize().map_err(|e| format!("cannot open {folder:?}: {e}"))?;
+        let index = LibraryIndex::open(&root).unwrap_or_else(|e| {
+            eprintln!("failed to load library index, rebuilding it: {e}");
+            LibraryIndex::empty(&root)
+        });
+
+        Ok(Self { root, config, index })
+    }
+
+    /// Bring the index up to date in memory
+    fn scan(&mut self) -> Result<(), String> {
+        let stats = self.index.scan(LibraryIndex::folders(&self.config))
+            .map_err(|e| format!("failed to scan the library: {e}"))?;
+        eprintln!("library index: {stats:?}");
+
+        Ok(())
+    }
+
+    /// Journal of the folder, finishing any move left over from an interrupted session
+    fn journal(&self) -> Result<Journal, String> {
+        Journal::recover(&self.root).map_err(|e| format!("failed to recover interrupted moves: {e}"))?;
+        Journal::open(&self.root).map_err(|e| format!("failed to open the move journal: {e}"))
+    }
+
+    /// Move a file through the journal, keeping the index up to date
+    fn move_file(
+        &mut self,
+        journal: &mut Journal,
+        source: &Path,
+        destination: &Path,
+        reverts: Option<u64>,
+    ) -> Result<(), String> {
+        journal.move_file(source, destination, reverts)
+            .map_err(|e| format!("failed to move {source:?} to {destination:?}: {e}"))?;
+
+        if let Err(e) = self.index.record_move(source, destination) {
+            eprintln!("failed to index {destination:?}: {e}");
+        }
+        Ok(())
+    }
+
+    fn save_index(&mut self) {
+        if let Err(e) = self.index.save() {
+            eprintln!("failed to save library index: {e}");
+        }
+    }
+
+    fn path(&self, entry: &Entry) -> PathBuf {
+        self.root.join(&entry.path)
+    }
+}
+
+// -- private items
+fn search(folder: &Path, query: &str) -> Result<(), String> {
+    let query: Query = query.parse().map_err(|e| format!("invalid query: {e}"))?;
+    let library = Library::read(folder)?;
+
+    for entry in query.filter(library.index.entries()) {
+        println!("{}", library.path(entry).display());
+    }
+
+    Ok(())
+}
+
+fn stats(folder: &Path) -> Result<(), String> {
+    let library = Library::read(folder)?;
+
+    let mut categories: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut tags: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut size = 0;
+    let mut count = 0;
+
+    for entry in library.index.entries() {
+        count += 1;
+        size += entry.size;
+        *categories.entry(entry.category.as_str()).or_default() += 1;
+        for tag in &entry.tags {
+            *tags.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+
+    let queued = SortQueue::get_file_list(&library.root, &library.config.formats)
+        .map_err(|e| format!("cannot list {:?}: {e}", library.root))?;
+
+    println!("sorted files: {count} ({})", format_size(size));
+    println!("left to sort: {}", queued.files.len());
+
+    if !queued.skipped.is_empty() {
+        println!("skipped: {}", queued.skipped.len());
+        for skipped in &queued.skipped {
+            println!("  {}: {}", skipped.name.to_string_lossy(), skipped.reason);
+        }
+    }
+
+    for (title, counts) in [("categories", categories), ("tags", tags)] {
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        println!("{title}:");
+        for (name, n) in counts {
+            let name = if name.is_empty() { "(none)" } else { name };
+            println!("  {n:>6}  {name}");
+        }
+    }
+
+    Ok(())
+}
+
+fn undo(folder: &Path, count: usize) -> Result<(), String> {
+    let mut library = Library::open(folder, false)?;
+    let mut journal = library.journal()?;
+
+    let moves = Journal::undoable(&library.root)
+        .map_err(|e| format!("failed to read the move journal: {e}"))?;
+
+    if moves.is_empty() {
+        println!("nothing to undo");
+    }
+
+    let res = moves.iter().rev().take(count).try_for_each(|m| {
+        // files rotated on the way in get their original contents back
+        let original = orientation::original_path(&library.root, m.id);
+        orientation::restore(&original, &m.destination)
+            .map_err(|e| format!("failed to restore the original of {}: {e}", m.destination.display()))?;
+
+        library.move_file(&mut journal, &m.destination, &m.source, Some(m.id))?;
+        println!("restored {}", m.source.display());
+        Ok(())
+    });
+
+    library.save_index();
+    res
+}
+
+fn reindex(folder: &Path, full: bool) -> Result<(), String> {
+    let mut library = Library::open(folder, false)?;
+    if full {
+        library.index = LibraryIndex::empty(&library.root);
+    }
+
+    library.scan()?;
+    library.save_index();
+    Ok(())
+}
+
+fn dedupe(folder: &Path, move_to: Option<&Path>) -> Result<(), String> {
+    let mut library = Library::open(folder, true)?;
+
+    let mut by_hash: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+    // the scan hashed every entry, unhashed ones would all be grouped together
+    for entry in library.index.entries().filter(|e| !e.hash.is_empty()) {
+        by_hash.entry(entry.hash.as_str()).or_default().push(entry);
+    }
+
+    // oldest copy first, it is the one kept
+    let groups: Vec<Vec<PathBuf>> = by_hash.into_values()
+        .filter(|g| g.len() > 1)
+        .map(|mut g| {
+            g.sort_by(|a, b| a.mtime.cmp(&b.mtime).then(a.path.cmp(&b.path)));
+            g.into_iter().map(|e| library.path(e)).collect()
+        })
+        .collect();
+
+    for group in &groups {
+        println!("{}", group[0].display());
+        for duplicate in &group[1..] {
+            println!("  duplicate: {}", duplicate.display());
+        }
+    }
+    println!("{} files with duplicates", groups.len());
+
+    let Some(move_to) = move_to else { return Ok(()) };
+
+    let target_dir = library.root.join(move_to);
+    fs::create_dir_all(&target_dir).map_err(|e| format!("cannot create {target_dir:?}: {e}"))?;
+    let mut journal = library.journal()?;
+
+    let res = groups.iter().flat_map(|g| &g[1..]).try_for_each(|duplicate| {
+        let destination = target_dir.join(duplicate.file_name().unwrap_or_default());
+        let destination = match file_store::resolve_collision(duplicate, &destination, CollisionPolicy::Suffix) {
+            Ok(Resolution::Free(p)) => p,
+            Ok(_) => unreachable!("suffix always picks a free path"),
+            Err(e) => return Err(format!("cannot move {duplicate:?}: {e}")),
+        };
+
+        library.move_file(&mut journal, duplicate, &destination, None)
+    });
+
+    library.save_index();
+    res
+}
+
+fn apply_rules(folder: &Path, dry_run: bool) -> Result<(), String> {
+    let mut library = Library::open(folder, true)?;
+
+    let rules = library.config.rules.iter()
+        .map(|r| {
+            let query: Query = r.query.parse()
+                .map_err(|e| format!("invalid rule query {:?}: {e}", r.query))?;
+            Ok((query, PathBuf::from(&r.path)))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if rules.is_empty() {
+        println!("no rules in the configuration");
+        return Ok(());
+    }
+
+    let moves: Vec<(PathBuf, PathBuf)> = library.index.entries().filter_map(|entry| {
+        let (_, dir) = rules.iter().find(|(query, _)| query.matches(entry))?;
+        let name = entry.path.file_name()?;
+
+        (entry.path.parent() != Some(dir.as_path()))
+            .then(|| (library.path(entry), library.root.join(dir).join(name)))
+    }).collect();
+
+    if dry_run {
+        for (source, destination) in &moves {
+            println!("would move {} -> {}", source.display(), destination.display());
+        }
+        return Ok(());
+    }
+
+    let mut journal = library.journal()?;
+    let collision = library.config.collision;
+
+    let res = moves.iter().try_for_each(|(source, destination)| {
+        if let Some(dir) = destination.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("cannot create {dir:?}: {e}"))?;
+        }
+
+        let resolution = file_store::resolve_collision(source, destination, collision)
+            .map_err(|e| format!("cannot move {source:?}: {e}"))?;
+
+        match resolution {
+            Resolution::Free(destination) => {
+                library.move_file(&mut journal, source, &destination, None)?;
+                println!("moved {} -> {}", source.display(), destination.display());
+            },
+            Resolution::Identical(existing) => {
+                println!("skipped {}, identical to {}", source.display(), existing.display());
+            },
+            Resolution::Conflict(existing) => {
+                println!("skipped {}, {} already exists", source.display(), existing.display());
+            },
+        }
+        Ok(())
+    });
+
+    library.save_index();
+    res
+}
+
+fn validate_config(folder: &Path) -> Result<(), String> {
+    let path = Config::find(folder);
+    if !path.exists() {
+        println!("no configuration in {folder:?}, the default one is used");
+        return Ok(());
+    }
+
+    let config = Config::load(folder).map_err(|e| e.to_string())?;
+    println!(
+        "{} is valid: {} buttons, {} categories, {} tags, {} rules",
+        path.display(),
+        config.buttons.len(),
+        config.categories.len(),
+        config.tags.len(),
+        config.rules.len(),
+    );
+
+    // validated by the load
+    if let Ok(bindings) = Bindings::new(&config) {
+        for warning in bindings.warnings() {
+            eprintln!("warning: {warning}, the button has no single key shortcut");
+        }
+    }
+
+    Ok(())
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
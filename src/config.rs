@@ -1,18 +1,71 @@
 //! Config structure
 //!
-//!
+//! Per folder configuration, loaded from `.image-sorter.{yaml,yml,json,toml}`
 
 /*--- Use Statments ------------------------------------------------------------------------------*/
 
+use std::{collections::{BTreeMap, BTreeSet, HashSet}, fmt, fs, io::Write, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_store::{DataStore, DataStoreError};
+use crate::format::ImageFormat;
+use crate::shortcuts::{Conflict, Bindings, Key, KeyCombo};
+use crate::sorted_name::{NAME_SEPARATOR, TAG_SEPARATOR};
+use crate::query::Query;
+
 /*--- Const --------------------------------------------------------------------------------------*/
 
+/// Base name of the per folder configuration file, the extension selects the format
+pub const CONFIG_FILE_STEM: &str = ".image-sorter";
+
+/// Extensions searched for, in order, the first one is used when creating a new config
+pub const CONFIG_FILE_EXTENSIONS: [&str; 4] = ["yaml", "yml", "json", "toml"];
+
+pub const DEFAULT_CONFIG_S: &str = include_str!("../default/config.yaml");
+
+lazy_static::lazy_static!{
+    pub static ref DEFAULT_CONFIG: Config = serde_yaml::from_str(DEFAULT_CONFIG_S)
+        .expect("failed to parse default configuration");
+}
+
 /*--- Types --------------------------------------------------------------------------------------*/
 
-/*--- Implementation -----------------------------------------------------------------------------*/
+/// Error found while loading a configuration file
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub kind: ConfigErrorKind,
+    /// 1-based line and column, if the parser reports one
+    pub location: Option<(usize, usize)>,
+    /// Contents of the offending line
+    pub line_text: Option<String>,
+}
 
-use std::collections::{HashSet, HashMap};
+#[derive(Debug)]
+pub enum ConfigErrorKind {
+    /// The file could not be read or parsed
+    Load(DataStoreError),
+    /// Keyboard shortcuts bound more than once
+    Shortcuts(Vec<Conflict>),
+    /// Unknown or repeated button ids in [Config::layout]
+    Layout(Vec<String>),
+    /// Invalid queries in [Config::rules]
+    Rules(Vec<String>),
+}
 
-use serde::{Deserialize, Serialize};
+/// Which of the name lists of [Config] to edit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameKind {
+    #[default]
+    Category,
+    Tag,
+}
+
+/// Optional row label and the `(id, button)` pairs in it
+pub type ButtonRow<'c> = (Option<&'c str>, Vec<(&'c str, &'c ButtonConfig)>);
+
+/*--- Implementation -----------------------------------------------------------------------------*/
 
 // pub mod default {
 //     use super::*;
@@ -29,21 +82,39 @@ structstruck::strike!{
         /// Top level categories
         ///
         ///
-        pub categories: HashSet<String>,
+        pub categories: BTreeSet<String>,
 
         /// Tags list
         ///
         /// All [Config::categories] can also be treated as tags
-        pub tags: HashSet<String>,
+        pub tags: BTreeSet<String>,
 
         /// Default output folder
         pub default_folder: String,
 
+        /// What to do when a file with the same name already exists at the destination
+        #[serde(default)]
+        pub collision:
+        #[derive(Copy, Default, PartialEq, Eq)]
+        #[serde(rename_all = "kebab-case")]
+        pub enum CollisionPolicy {
+            /// Append a number to the new file name: `name_1.png`
+            #[default]
+            Suffix,
+            /// Append the start of the content hash: `name_1a2b3c4d.png`
+            Hash,
+            /// Leave the file in the queue if the existing one has the same contents, otherwise
+            /// append a number
+            SkipIdentical,
+            /// Ask what to do
+            Prompt,
+        },
+
         /// Button mappings
         ///
-        /// HashMap of shortcut key (vim-like) to values
+        /// Map of shortcut key (vim-like) to values, shown in the order of [Config::layout]
         pub buttons:
-        HashMap<String,
+        BTreeMap<String,
         pub struct ButtonConfig {
             /// Long Label
             pub label: String,
@@ -54,10 +125,513 @@ structstruck::strike!{
             /// Output path
             pub path: String,
 
-            // the key itself if the shortcut
-            // pub shortcut: char,
+            /// Shortcut, defaults to `C-<id>`
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub shortcut: Option<KeyCombo>,
+        }>,
+
+        /// Button rows, in display order
+        ///
+        /// Buttons not listed in any row are shown after these, sorted by id
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub layout: Vec<
+        pub struct ButtonGroup {
+            /// Shown above the row
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub label: Option<String>,
+
+            /// Button ids, in order
+            pub buttons: Vec<String>,
+        }>,
+
+        /// Rules used by `apply-rules`, sorted files are moved to the folder of the first rule whose
+        /// query matches them
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub rules: Vec<
+        pub struct Rule {
+            /// Library query, see [query](crate::query)
+            pub query: String,
+
+            /// Destination folder
+            pub path: String,
         }>,
+
+        /// Filter used to shrink images larger than the window
+        #[serde(default)]
+        pub scale_filter:
+        #[derive(Copy, Default, PartialEq, Eq)]
+        #[serde(rename_all = "kebab-case")]
+        pub enum ScaleFilter {
+            /// Fastest, blocky
+            Nearest,
+            #[default]
+            Triangle,
+            CatmullRom,
+            Gaussian,
+            /// Sharpest, slowest
+            Lanczos3,
+        },
+
+        /// Image formats to sort, other files are left in the folder and reported
+        #[serde(default = "default_formats")]
+        pub formats: BTreeSet<ImageFormat>,
+
+        /// Rotate the pixels of JPEGs with an EXIF orientation when they are sorted, re-encoding
+        /// them, see [orientation::bake](crate::orientation::bake). The originals are kept for undo
+        #[serde(default)]
+        pub bake_orientation: bool,
+
+        /// Maximum number of buttons on a single row, longer rows wrap
+        #[serde(default = "default_button_columns")]
+        pub button_columns: usize,
+
+        /// Keyboard shortcuts for the fixed controls
+        #[serde(default)]
+        pub shortcuts:
+        #[derive(Copy)]
+        #[serde(default)]
+        pub struct ShortcutConfig {
+            pub next: KeyCombo,
+            pub prev: KeyCombo,
+            pub undo: KeyCombo,
+            pub redo: KeyCombo,
+            /// Open in the default program
+            pub open: KeyCombo,
+            /// Move to the default folder
+            pub confirm: KeyCombo,
+            /// Zoom of the shown image
+            pub zoom_fit: KeyCombo,
+            pub zoom_fill_width: KeyCombo,
+            /// One image pixel per screen pixel
+            pub zoom_actual: KeyCombo,
+            pub zoom_in: KeyCombo,
+            pub zoom_out: KeyCombo,
+            /// Scroll the zoomed image
+            pub pan_up: KeyCombo,
+            pub pan_down: KeyCombo,
+            pub pan_left: KeyCombo,
+            pub pan_right: KeyCombo,
+        },
+
+        /// Single key commands for fast sort (idle) mode
+        #[serde(default)]
+        pub fast_sort:
+        #[derive(Copy)]
+        #[serde(default)]
+        pub struct FastSortConfig {
+            /// Focus the filename box
+            pub input: KeyCombo,
+            /// Leave the filename box, works while typing
+            pub idle: KeyCombo,
+            pub next: KeyCombo,
+            pub prev: KeyCombo,
+            pub undo: KeyCombo,
+            pub redo: KeyCombo,
+            pub open: KeyCombo,
+            pub zoom_in: KeyCombo,
+            pub zoom_out: KeyCombo,
+            pub pan_up: KeyCombo,
+            pub pan_down: KeyCombo,
+            pub pan_left: KeyCombo,
+            pub pan_right: KeyCombo,
+        },
     },
 }
 
+impl Config {
+    /// Find the configuration file in `folder`
+    ///
+    /// Returns the path of the first existing file, or the default (YAML) path if none exists
+    pub fn find(folder: &Path) -> PathBuf {
+        CONFIG_FILE_EXTENSIONS.iter()
+            .map(|ext| folder.join(format!("{CONFIG_FILE_STEM}.{ext}")))
+            .find(|p| p.is_file())
+            .unwrap_or_else(|| folder.join(format!("{CONFIG_FILE_STEM}.{}", CONFIG_FILE_EXTENSIONS[0])))
+    }
+
+    /// Load the configuration for `folder`
+    ///
+    /// If no config file exists a new one is created from the default config. A config that fails
+    /// to parse is reported as an error and never replaced with the default.
+    pub fn load(folder: &Path) -> Result<DataStore<Config>, ConfigError> {
+        let cfg_path = Self::find(folder);
+
+        if !cfg_path.exists() {
+            eprintln!("no local config present, creating config file {cfg_path:?}");
+
+            fs::OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(&cfg_path)
+                .and_then(|mut f| f.write_all(DEFAULT_CONFIG_S.as_bytes()))
+                .map_err(|e| ConfigError::new(&cfg_path, e.into()))?;
+        }
+
+        Self::read(folder)
+    }
+
+    /// Load the configuration for `folder` like [Config::load], without creating a file
+    ///
+    /// The default config is used when there is none.
+    pub fn read(folder: &Path) -> Result<DataStore<Config>, ConfigError> {
+        let cfg_path = Self::find(folder);
+
+        let config: DataStore<Config> = if cfg_path.exists() {
+            DataStore::new_from_path(&cfg_path).map_err(|e| ConfigError::new(&cfg_path, e))?
+        } else {
+            DataStore::new_from_data(&cfg_path, DEFAULT_CONFIG.clone())
+        };
+
+        let invalid = |kind| ConfigError { path: cfg_path.clone(), kind, location: None, line_text: None };
+
+        if let Err(e) = Bindings::new(&config) {
+            return Err(invalid(ConfigErrorKind::Shortcuts(e)));
+        }
+
+        if let Err(e) = config.validate_layout() {
+            return Err(invalid(ConfigErrorKind::Layout(e)));
+        }
+
+        if let Err(e) = config.validate_rules() {
+            return Err(invalid(ConfigErrorKind::Rules(e)));
+        }
+
+        Ok(config)
+    }
+
+    /// Categories or tags, see [NameKind]
+    pub fn names(&self, kind: NameKind) -> &BTreeSet<String> {
+        match kind {
+            NameKind::Category => &self.categories,
+            NameKind::Tag => &self.tags,
+        }
+    }
+
+    pub fn names_mut(&mut self, kind: NameKind) -> &mut BTreeSet<String> {
+        match kind {
+            NameKind::Category => &mut self.categories,
+            NameKind::Tag => &mut self.tags,
+        }
+    }
+
+    /// Add a category or tag, returns whether it was new
+    pub fn add_name(&mut self, kind: NameKind, name: &str) -> Result<bool, String> {
+        let name = Self::normalize_name(name)?;
+        Ok(self.names_mut(kind).insert(name))
+    }
+
+    /// Rename a category or tag, returns whether `from` existed
+    pub fn rename_name(&mut self, kind: NameKind, from: &str, to: &str) -> Result<bool, String> {
+        let to = Self::normalize_name(to)?;
+        let names = self.names_mut(kind);
+
+        if !names.remove(from) {
+            return Ok(false);
+        }
+
+        names.insert(to);
+        Ok(true)
+    }
+
+    /// Remove a category or tag, returns whether it existed
+    pub fn remove_name(&mut self, kind: NameKind, name: &str) -> bool {
+        self.names_mut(kind).remove(name)
+    }
+
+    /// Trim a new category or tag name, rejecting names that can't be part of a filename segment
+    pub fn normalize_name(name: &str) -> Result<String, String> {
+        let name = name.trim();
+
+        if name.is_empty() {
+            Err("name is empty".to_string())
+        } else if name.contains(TAG_SEPARATOR) || name.contains(NAME_SEPARATOR) {
+            Err(format!("{name:?} contains a filename separator"))
+        } else if name.contains(['/', '\\']) {
+            Err(format!("{name:?} contains a path separator"))
+        } else {
+            Ok(name.to_string())
+        }
+    }
+
+    /// Buttons grouped in rows, following [Config::layout]
+    ///
+    /// Buttons missing from the layout are put in a last unlabeled row
+    pub fn button_rows(&self) -> Vec<ButtonRow<'_>> {
+        let mut listed = HashSet::new();
+
+        let mut rows: Vec<_> = self.layout.iter().map(|group| {
+            let buttons = group.buttons.iter()
+                .filter_map(|id| self.buttons.get_key_value(id))
+                .map(|(id, b_cfg)| {
+                    listed.insert(id.as_str());
+                    (id.as_str(), b_cfg)
+                })
+                .collect();
+
+            (group.label.as_deref(), buttons)
+        }).collect();
+
+        let rest: Vec<_> = self.buttons.iter()
+            .filter(|(id, _)| !listed.contains(id.as_str()))
+            .map(|(id, b_cfg)| (id.as_str(), b_cfg))
+            .collect();
+
+        if !rest.is_empty() {
+            rows.push((None, rest));
+        }
+
+        rows
+    }
+
+    /// Check that the layout only references existing buttons, once
+    pub fn validate_layout(&self) -> Result<(), Vec<String>> {
+        let mut seen = HashSet::new();
+        let mut errors = vec![];
+
+        for id in self.layout.iter().flat_map(|g| &g.buttons) {
+            if !self.buttons.contains_key(id) {
+                errors.push(format!("layout references unknown button {id:?}"));
+            } else if !seen.insert(id) {
+                errors.push(format!("button {id:?} appears more than once in the layout"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check that every rule has a valid query and a destination
+    pub fn validate_rules(&self) -> Result<(), Vec<String>> {
+        let errors: Vec<_> = self.rules.iter().enumerate().flat_map(|(i, rule)| {
+            let query = rule.query.parse::<Query>().err()
+                .map(|e| format!("rule {} has an invalid query {:?}: {e}", i + 1, rule.query));
+            let path = rule.path.trim().is_empty()
+                .then(|| format!("rule {} has no destination path", i + 1));
+
+            query.into_iter().chain(path)
+        }).collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn default_button_columns() -> usize {
+    6
+}
+
+fn default_formats() -> BTreeSet<ImageFormat> {
+    ImageFormat::ALL.into_iter().collect()
+}
+
+impl Default for ShortcutConfig {
+    fn default() -> Self {
+        Self {
+            next: KeyCombo::ctrl(Key::Right),
+            prev: KeyCombo::ctrl(Key::Left),
+            undo: KeyCombo::ctrl(Key::Char('z')),
+            redo: KeyCombo::ctrl(Key::Char('y')),
+            open: KeyCombo::ctrl(Key::Char('o')),
+            confirm: KeyCombo::new(Key::Enter),
+            zoom_fit: KeyCombo::alt(Key::Char('0')),
+            zoom_fill_width: KeyCombo::alt(Key::Char('w')),
+            zoom_actual: KeyCombo::alt(Key::Char('1')),
+            zoom_in: KeyCombo::alt(Key::PageUp),
+            zoom_out: KeyCombo::alt(Key::PageDown),
+            pan_up: KeyCombo::alt(Key::Up),
+            pan_down: KeyCombo::alt(Key::Down),
+            pan_left: KeyCombo::alt(Key::Left),
+            pan_right: KeyCombo::alt(Key::Right),
+        }
+    }
+}
+
+impl Default for FastSortConfig {
+    fn default() -> Self {
+        Self {
+            input: KeyCombo::new(Key::Char('i')),
+            idle: KeyCombo::new(Key::Escape),
+            next: KeyCombo::new(Key::Right),
+            prev: KeyCombo::new(Key::Left),
+            undo: KeyCombo::new(Key::Char('u')),
+            redo: KeyCombo { shift: true, ..KeyCombo::new(Key::Char('u')) },
+            open: KeyCombo::new(Key::Char('o')),
+            zoom_in: KeyCombo::new(Key::PageUp),
+            zoom_out: KeyCombo::new(Key::PageDown),
+            pan_up: KeyCombo::new(Key::Up),
+            pan_down: KeyCombo::new(Key::Down),
+            pan_left: KeyCombo { shift: true, ..KeyCombo::new(Key::Left) },
+            pan_right: KeyCombo { shift: true, ..KeyCombo::new(Key::Right) },
+        }
+    }
+}
+
+impl ConfigError {
+    /// Error while reading or parsing the file at `path`
+    pub fn new(path: &Path, source: DataStoreError) -> Self {
+        let location = source.location();
+
+        let line_text = location.and_then(|(line, _)| {
+            fs::read_to_string(path).ok()?
+                .lines()
+                .nth(line.checked_sub(1)?)
+                .map(str::to_string)
+        });
+
+        Self {
+            path: path.to_path_buf(),
+            kind: ConfigErrorKind::Load(source),
+            location,
+            line_text,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+
+        if let Some((line, col)) = self.location {
+            write!(f, ":{line}:{col}")?;
+        }
+
+        match &self.kind {
+            ConfigErrorKind::Load(e) => write!(f, ": {e}")?,
+            ConfigErrorKind::Shortcuts(errors) => {
+                write!(f, ": invalid shortcuts")?;
+                for e in errors {
+                    write!(f, "\n    {e}")?;
+                }
+            },
+            ConfigErrorKind::Layout(errors) => {
+                write!(f, ": invalid button layout")?;
+                for e in errors {
+                    write!(f, "\n    {e}")?;
+                }
+            },
+            ConfigErrorKind::Rules(errors) => {
+                write!(f, ": invalid rules")?;
+                for e in errors {
+                    write!(f, "\n    {e}")?;
+                }
+            },
+        }
+
+        if let (Some(text), Some((_, col))) = (&self.line_text, self.location) {
+            write!(f, "\n    {text}\n    {:>col$}", "^")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ConfigErrorKind::Load(e) => Some(e),
+            ConfigErrorKind::Shortcuts(_)
+            | ConfigErrorKind::Layout(_)
+            | ConfigErrorKind::Rules(_) => None,
+        }
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder_with(name: &str, contents: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(name), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn malformed_yaml_points_at_the_error() {
+        let dir = folder_with(".image-sorter.yaml", "default_folder: \"output\"\nbutton_columns: many\n");
+
+        let Err(e) = Config::read(dir.path()) else { panic!("loaded a broken config") };
+        assert!(matches!(e.kind, ConfigErrorKind::Load(DataStoreError::Yaml(_))), "{e:?}");
+        assert_eq!(e.location, Some((2, 17)));
+        assert_eq!(e.line_text.as_deref(), Some("button_columns: many"));
+
+        // the caret is under the 17th character of the line
+        let shown = e.to_string();
+        assert!(shown.contains(".image-sorter.yaml:2:17: yaml error: "), "{shown}");
+        assert!(shown.ends_with(&format!("\n    button_columns: many\n    {}^", " ".repeat(16))), "{shown}");
+    }
+
+    #[test]
+    fn json_config() {
+        let mut config = DEFAULT_CONFIG.clone();
+        config.default_folder = "sorted".to_string();
+        let dir = folder_with(".image-sorter.json", &serde_json::to_string_pretty(&config).unwrap());
+
+        assert_eq!(Config::find(dir.path()), dir.path().join(".image-sorter.json"));
+        let Ok(loaded) = Config::read(dir.path()) else { panic!("failed to load the JSON config") };
+        assert_eq!(loaded.format(), crate::data_store::Format::Json);
+        assert_eq!(loaded.default_folder, "sorted");
+
+        fs::write(dir.path().join(".image-sorter.json"), "{\n  \"default_folder\": }\n").unwrap();
+        let Err(e) = Config::read(dir.path()) else { panic!("loaded a broken config") };
+        assert!(matches!(e.kind, ConfigErrorKind::Load(DataStoreError::Json(_))), "{e:?}");
+        assert_eq!(e.location.map(|(line, _)| line), Some(2));
+    }
+
+    #[test]
+    fn yaml_is_found_first() {
+        let dir = folder_with(".image-sorter.toml", "");
+        fs::write(dir.path().join(".image-sorter.yml"), "").unwrap();
+        assert_eq!(Config::find(dir.path()), dir.path().join(".image-sorter.yml"));
+
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(Config::find(empty.path()), empty.path().join(".image-sorter.yaml"));
+        assert!(Config::read(empty.path()).is_ok());
+        assert!(!Config::find(empty.path()).exists(), "reading never creates a config");
+    }
+
+    #[test]
+    fn invalid_layout() {
+        let mut config = DEFAULT_CONFIG.clone();
+        config.layout.push(ButtonGroup { label: None, buttons: vec!["d".to_string(), "q".to_string()] });
+        let dir = folder_with(".image-sorter.yaml", &serde_yaml::to_string(&config).unwrap());
+
+        let Err(e) = Config::read(dir.path()) else { panic!("loaded an invalid layout") };
+        let ConfigErrorKind::Layout(errors) = &e.kind else { panic!("{e:?}") };
+        assert_eq!(errors, &[
+            "button \"d\" appears more than once in the layout".to_string(),
+            "layout references unknown button \"q\"".to_string(),
+        ]);
+        let shown = e.to_string();
+        let expected = format!(": invalid button layout\n    {}\n    {}", errors[0], errors[1]);
+        assert!(shown.ends_with(&expected), "{shown}");
+    }
+
+    #[test]
+    fn invalid_shortcuts_and_rules() {
+        let mut config = DEFAULT_CONFIG.clone();
+        config.shortcuts.redo = config.shortcuts.undo;
+        let dir = folder_with(".image-sorter.yaml", &serde_yaml::to_string(&config).unwrap());
+
+        let Err(e) = Config::read(dir.path()) else { panic!("loaded conflicting shortcuts") };
+        assert!(matches!(&e.kind, ConfigErrorKind::Shortcuts(c) if c.len() == 1), "{e:?}");
+
+        let mut config = DEFAULT_CONFIG.clone();
+        config.rules.push(Rule { query: "(anime".to_string(), path: " ".to_string() });
+        fs::write(dir.path().join(".image-sorter.yaml"), serde_yaml::to_string(&config).unwrap()).unwrap();
+
+        let Err(e) = Config::read(dir.path()) else { panic!("loaded invalid rules") };
+        let ConfigErrorKind::Rules(errors) = &e.kind else { panic!("{e:?}") };
+        assert_eq!(errors.len(), 2, "{errors:?}");
+    }
+}
+
 /*--------------------------------------------- EOF ----------------------------------------------*/
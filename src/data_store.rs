@@ -1,69 +1,236 @@
+//! Persistent data storage
 //!
+//! Wraps a serializable value together with the file it was read from, the on-disk format is
+//! picked from the file extension (see [Format]).
 
 #![cfg_attr(debug_assertions, allow(unused))]
 
-// TODO: later allow choosing the deserializer
-
 /*--- Use ----------------------------------------------------------------------------------------*/
 
 use core::ops::{DerefMut, Deref};
 
-use std::{io, path::{PathBuf, Path}, fs::OpenOptions,};
+use std::{
+    io::{self, Write},
+    path::{PathBuf, Path},
+    fs::{self, OpenOptions},
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use serde::{Deserialize, Serialize};
 
-/*--- Impl ---------------------------------------------------------------------------------------*/
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Source of [DataStore::generation] values, shared so they are unique across stores
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/*--- Types --------------------------------------------------------------------------------------*/
 
-// pub type DataSer<D> = fn(&D) -> Result<String, E>;
-// pub type DataDeser<D> = fn(&str) -> Result<D, ()>;
+/// Serialization formats supported by [DataStore]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
 
+/// Errors returned when loading or saving a [DataStore]
+#[derive(Debug)]
+pub enum DataStoreError {
+    Io(io::Error),
+    /// The file extension does not map to any known [Format]
+    UnknownFormat(PathBuf),
+    Yaml(serde_yaml::Error),
+    Json(serde_json::Error),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
+}
 
+/// Serializable data bound to a file path
+#[derive(Clone)]
 pub struct DataStore<D> {
     data: D,
     path: PathBuf,
+    format: Format,
+    /// Bumped on every mutable access, lets callers cache values derived from the data
+    generation: u64,
 }
 
-type DataStoreError = serde_yaml::Error;
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Format {
+    /// Pick the format from the extension of `path`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match ext.as_str() {
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn serialize<D: Serialize>(self, data: &D) -> Result<String, DataStoreError> {
+        Ok(match self {
+            Self::Yaml => serde_yaml::to_string(data)?,
+            Self::Json => serde_json::to_string_pretty(data)?,
+            // through a value, it puts the tables after the plain values as TOML requires
+            Self::Toml => toml::to_string_pretty(&toml::Value::try_from(data)?)?,
+        })
+    }
+
+    pub fn deserialize<D>(self, s: &str) -> Result<D, DataStoreError> where for<'d> D: Deserialize<'d> {
+        Ok(match self {
+            Self::Yaml => serde_yaml::from_str(s)?,
+            Self::Json => serde_json::from_str(s)?,
+            Self::Toml => toml::from_str(s)?,
+        })
+    }
+}
 
 impl<D> DataStore<D> where for<'d> D: Deserialize<'d> + Serialize {
 
-    /// new from path
+    /// Read and deserialize the data at `source`
     ///
-    /// Using default deserializer
-    // TODO: add error type
-    pub fn new_from_path(source: &Path) -> Result<Self, ()> {
-        let mut f = OpenOptions::new()
-            .read(true)
-            .open(source)
-            .map_err(|e| eprintln!("error: {e}"))?;
-
-        let s = Self {
-            data: serde_yaml::from_reader(&mut f)
-                .map_err(|e| eprintln!("serde yaml error: {e}"))?,
+    /// The format is chosen based on the file extension
+    pub fn new_from_path(source: &Path) -> Result<Self, DataStoreError> {
+        let format = Format::from_path(source)
+            .ok_or_else(|| DataStoreError::UnknownFormat(source.to_path_buf()))?;
+
+        Self::new_with_format(source, format)
+    }
+
+    /// Read and deserialize the data at `source` using an explicit format
+    pub fn new_with_format(source: &Path, format: Format) -> Result<Self, DataStoreError> {
+        let s = fs::read_to_string(source)?;
+
+        Ok(Self {
+            data: format.deserialize(&s)?,
             path: source.to_path_buf(),
-            // deserializer,
-            // serializer,
-        };
-        Ok(s)
-        // Self::new_with_deserializer(source, serde_yaml::to_string::<D>, serde_yaml::from_str::<D>)
+            format,
+            generation: next_generation(),
+        })
     }
 
+    /// Wrap already loaded data, nothing is written until [DataStore::save] is called
+    ///
+    /// Falls back to YAML if the format cannot be inferred from `source`
     pub fn new_from_data(source: &Path, data: D) -> Self {
         Self {
+            format: Format::from_path(source).unwrap_or(Format::Yaml),
             path: source.to_path_buf(),
+            generation: next_generation(),
             data,
         }
     }
 
-    // pub fn new_with_deserializer(
-    //     source: &Path,
-    //     serializer: DataSer<D>,
-    //     deserializer: DataDeser<D>
-    // ) -> Result<Self, ()> {
-    // }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
 
-    pub fn save(&mut self) -> io::Error {
-        todo!()
+    /// Changes every time the data is borrowed mutably, never repeats between stores
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Path of the backup copy kept from the previous save
+    pub fn backup_path(&self) -> PathBuf {
+        Self::sibling(&self.path, "bak")
+    }
+
+    /// Write the data back to disk
+    ///
+    /// The new contents are written to a temporary file in the same directory and renamed over
+    /// the old one, so the file is either the previous or the new version, never a partial write.
+    /// The previous version is kept alongside as `<file>.bak`.
+    pub fn save(&mut self) -> Result<(), DataStoreError> {
+        let contents = self.format.serialize(&self.data)?;
+        self.write(&contents, true)
+    }
+
+    /// [DataStore::save] without keeping the previous version, for data that can be rebuilt
+    pub fn save_without_backup(&mut self) -> Result<(), DataStoreError> {
+        let contents = self.format.serialize(&self.data)?;
+        self.write(&contents, false)
+    }
+
+    /// Save only the top level `fields` of the data, the rest of the file is kept as it is
+    ///
+    /// Fields missing from the file aren't added with their default value and fields the data
+    /// doesn't know about are kept. The file is still serialized again, so comments are lost.
+    /// Saves everything if the file doesn't exist yet.
+    pub fn save_fields(&mut self, fields: &[&str]) -> Result<(), DataStoreError> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return self.save(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // YAML values keep the order of the keys in the file
+        let mut file: serde_yaml::Value = self.format.deserialize(&current)?;
+        let data = serde_yaml::to_value(&self.data)?;
+
+        let (Some(file_map), Some(data_map)) = (file.as_mapping_mut(), data.as_mapping()) else {
+            return self.save();
+        };
+
+        for field in fields {
+            match data_map.get(*field) {
+                Some(value) => file_map.insert((*field).into(), value.clone()),
+                None => file_map.remove(*field),
+            };
+        }
+
+        let contents = self.format.serialize(&file)?;
+        self.write(&contents, true)
+    }
+
+    // -- private items
+    /// Replace the file with `contents`, keeping the previous version as a backup if `backup` is set
+    fn write(&self, contents: &str, backup: bool) -> Result<(), DataStoreError> {
+        let tmp_path = Self::sibling(&self.path, "tmp");
+
+        {
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_path)?;
+
+            f.write_all(contents.as_bytes())?;
+            f.sync_all()?;
+        }
+
+        if backup && self.path.exists() {
+            if let Err(e) = fs::copy(&self.path, self.backup_path()) {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e.into());
+            }
+        }
+
+        if let Err(e) = fs::rename(&tmp_path, &self.path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+
+        // make the rename itself durable, not supported on every platform so errors are ignored
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            let _ = fs::File::open(parent).and_then(|d| d.sync_all());
+        }
+
+        Ok(())
+    }
+
+    fn sibling(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(suffix);
+        path.with_file_name(name)
     }
 }
 
@@ -76,8 +243,184 @@ impl<D> Deref for DataStore<D> {
 
 impl<D> DerefMut for DataStore<D> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.generation = next_generation();
         &mut self.data
     }
 }
 
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl DataStoreError {
+    /// 1-based line and column of a parse error, if the parser reports one
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Yaml(e) => e.location().map(|l| (l.line(), l.column())),
+            Self::Json(e) if e.line() > 0 => Some((e.line(), e.column())),
+            Self::TomlDe(e) => e.line_col().map(|(l, c)| (l + 1, c + 1)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::UnknownFormat(p) => write!(f, "unknown data format for {p:?}"),
+            Self::Yaml(e) => write!(f, "yaml error: {e}"),
+            Self::Json(e) => write!(f, "json error: {e}"),
+            Self::TomlDe(e) => write!(f, "toml error: {e}"),
+            Self::TomlSer(e) => write!(f, "toml error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DataStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::UnknownFormat(_) => None,
+            Self::Yaml(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::TomlDe(e) => Some(e),
+            Self::TomlSer(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for DataStoreError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_yaml::Error> for DataStoreError {
+    fn from(e: serde_yaml::Error) -> Self {
+        Self::Yaml(e)
+    }
+}
+
+impl From<serde_json::Error> for DataStoreError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<toml::de::Error> for DataStoreError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::TomlDe(e)
+    }
+}
+
+impl From<toml::ser::Error> for DataStoreError {
+    fn from(e: toml::ser::Error) -> Self {
+        Self::TomlSer(e)
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Data {
+        #[serde(default)]
+        names: Vec<String>,
+        #[serde(default)]
+        count: u32,
+    }
+
+    #[test]
+    fn format_from_the_extension() {
+        assert_eq!(Format::from_path(Path::new("a.yml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("a.YAML")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("a.json")), Some(Format::Json));
+        assert_eq!(Format::from_path(Path::new("dir.d/a.toml")), Some(Format::Toml));
+        assert_eq!(Format::from_path(Path::new("a.txt")), None);
+        assert_eq!(Format::from_path(Path::new("yaml")), None);
+
+        let err = DataStore::<Data>::new_from_path(Path::new("a.txt")).err();
+        assert!(matches!(err, Some(DataStoreError::UnknownFormat(_))), "{err:?}");
+        assert_eq!(DataStore::new_from_data(Path::new("a.txt"), Data::default()).format(), Format::Yaml);
+    }
+
+    #[test]
+    fn save_keeps_the_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+
+        let mut store = DataStore::new_from_data(&path, Data { names: vec![], count: 1 });
+        store.save().unwrap();
+        assert!(!store.backup_path().exists(), "nothing to back up on the first save");
+
+        store.count = 2;
+        store.save().unwrap();
+
+        let backup: DataStore<Data> = DataStore::new_with_format(&store.backup_path(), Format::Json).unwrap();
+        let saved: DataStore<Data> = DataStore::new_from_path(&path).unwrap();
+        assert_eq!(backup.count, 1);
+        assert_eq!(saved.count, 2);
+        assert!(!dir.path().join("data.json.tmp").exists());
+    }
+
+    #[test]
+    fn failed_save_removes_the_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.yaml");
+        // a folder can't be backed up or replaced by a file
+        fs::create_dir(&path).unwrap();
+
+        let mut store = DataStore::new_from_data(&path, Data::default());
+        assert!(matches!(store.save(), Err(DataStoreError::Io(_))));
+        assert!(!dir.path().join("data.yaml.tmp").exists());
+        assert!(path.is_dir());
+    }
+
+    #[test]
+    fn save_fields_leaves_the_rest_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.yaml");
+        fs::write(&path, "# comment\nunknown: {a: 1}\nnames: [x]\n").unwrap();
+
+        let mut store: DataStore<Data> = DataStore::new_from_path(&path).unwrap();
+        store.names.push("y".to_string());
+        store.save_fields(&["names"]).unwrap();
+
+        let saved: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str("unknown: {a: 1}\nnames: [x, y]").unwrap();
+        assert_eq!(saved, expected, "the defaulted count isn't written");
+    }
+
+    #[test]
+    fn save_fields_in_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.toml");
+        fs::write(&path, "count = 2\n\n[unknown]\na = 1\n").unwrap();
+
+        let mut store: DataStore<Data> = DataStore::new_from_path(&path).unwrap();
+        store.names.push("y".to_string());
+        store.save_fields(&["names"]).unwrap();
+
+        let saved: toml::Value = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let expected: toml::Value = toml::from_str("count = 2\nnames = [\"y\"]\n[unknown]\na = 1").unwrap();
+        assert_eq!(saved, expected);
+    }
+
+    #[test]
+    fn save_fields_without_a_file_saves_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+
+        let mut store = DataStore::new_from_data(&path, Data { names: vec![], count: 3 });
+        store.save_fields(&["names"]).unwrap();
+
+        let saved: DataStore<Data> = DataStore::new_from_path(&path).unwrap();
+        assert_eq!(*saved, Data { names: vec![], count: 3 });
+    }
+}
+
 /*--------------------------------------------- EOF ----------------------------------------------*/
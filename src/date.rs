@@ -0,0 +1,70 @@
+//! Calendar dates
+//!
+//! Conversion between proleptic gregorian dates and days since the unix epoch, without time zones.
+//! Used to parse the dates of [queries](crate::query) and to show file times.
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+/// Days since 1970-01-01 of a proleptic gregorian date
+pub fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+/// Proleptic gregorian date of a day since 1970-01-01, the inverse of [days_from_civil]
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (yoe + era * 400 + i64::from(m <= 2), m, d)
+}
+
+pub fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn round_trip() {
+        for days in (-800_000..800_000).step_by(97) {
+            let (y, m, d) = civil_from_days(days);
+            assert!((1..=days_in_month(y, m)).contains(&d), "{y}-{m}-{d}");
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,496 @@
+//! Background image decoding
+//!
+//! A pool of worker threads decodes images ahead of time into a memory bounded cache, so moving
+//! through the queue doesn't wait for the decoder. Photos are turned upright following their EXIF
+//! orientation and images larger than the window are shrunk there as well, textures are only ever
+//! made from the display sized version. Images already decoded are rescaled there too, when the
+//! window size changes or a zoom needs more detail.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread,
+};
+
+use nannou::image::{self, imageops::FilterType, DynamicImage, GenericImageView};
+
+use image_sorter::{config::ScaleFilter, orientation::Orientation};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Images decoded ahead on each side of the current one
+pub const PREFETCH: usize = 3;
+
+/// Memory for decoded images, the least recently used ones are dropped past it
+pub const CACHE_BYTES: usize = 512 * 1024 * 1024;
+
+/// Upper bound of decoder threads
+const MAX_WORKERS: usize = 4;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Decoded image, `None` if the file couldn't be decoded
+pub type Decoded = Option<Arc<Frame>>;
+
+/// Largest size images are shown at and how they are shrunk to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    /// Width and height in pixels
+    pub bound: [u32; 2],
+    pub filter: ScaleFilter,
+}
+
+/// Decoded image and its version for display
+pub struct Frame {
+    /// Full resolution
+    pub original: Arc<DynamicImage>,
+    /// Fits in [Scale::bound], the original itself if it already does
+    pub display: Arc<DynamicImage>,
+    pub scale: Scale,
+}
+
+/// Decoder pool with its cache
+pub struct Decoder {
+    shared: Arc<Shared>,
+    results: mpsc::Receiver<Done>,
+    /// Queued or being decoded
+    pending: HashSet<PathBuf>,
+    /// Rescales queued or running
+    rescaling: Vec<(PathBuf, Scale)>,
+    /// Last rescale finished for a scale other than the current one, see [Decoder::take_rescaled]
+    rescaled: Option<(PathBuf, Arc<Frame>)>,
+    /// Current scale, as in [Shared::scale]
+    scale: Scale,
+    cache: Cache,
+}
+
+/// Work for the decoder threads
+enum Job {
+    Decode(PathBuf),
+    Rescale(PathBuf, Arc<DynamicImage>, Scale),
+}
+
+/// Result of a [Job]
+enum Done {
+    Decoded(PathBuf, Decoded),
+    Rescaled(PathBuf, Arc<Frame>),
+}
+
+/// State shared with the workers
+struct Shared {
+    jobs: Mutex<Jobs>,
+    ready: Condvar,
+    /// Applied to the images decoded from now on
+    scale: Mutex<Scale>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    /// Rescales first, then the decodes in order
+    queue: VecDeque<Job>,
+    /// Set when the [Decoder] is dropped, workers stop
+    closed: bool,
+}
+
+/// Least recently used cache bounded by the decoded size
+struct Cache {
+    images: HashMap<PathBuf, (Decoded, u64)>,
+    bytes: usize,
+    budget: usize,
+    /// Incremented on every access, entries keep the value of their last use
+    clock: u64,
+    /// Never dropped to make room, the image waited for
+    pinned: Option<PathBuf>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Decoder {
+    pub fn new(budget: usize, scale: Scale) -> Self {
+        let workers = thread::available_parallelism().map_or(2, |n| n.get()).min(MAX_WORKERS);
+
+        let shared = Arc::new(Shared {
+            jobs: Mutex::default(),
+            ready: Condvar::new(),
+            scale: Mutex::new(scale),
+        });
+        let (sender, results) = mpsc::channel();
+
+        for i in 0..workers {
+            let shared = shared.clone();
+            let sender = sender.clone();
+
+            let spawned = thread::Builder::new()
+                .name(format!("decoder-{i}"))
+                .spawn(move || worker(&shared, &sender));
+
+            if let Err(e) = spawned {
+                eprintln!("failed to start decoder thread: {e}");
+            }
+        }
+
+        Self {
+            shared,
+            results,
+            pending: HashSet::new(),
+            rescaling: vec![],
+            rescaled: None,
+            scale,
+            cache: Cache { images: HashMap::new(), bytes: 0, budget, clock: 0, pinned: None },
+        }
+    }
+
+    /// Cached image of `path`, `None` if it isn't decoded yet
+    pub fn get(&mut self, path: &Path) -> Option<Decoded> {
+        self.cache.get(path)
+    }
+
+    /// Keep the image of `path` cached even past the budget, until another one is pinned
+    ///
+    /// The wanted image may arrive in the same [Self::poll] as its neighbours, which would otherwise
+    /// push it out before it's shown.
+    pub fn pin(&mut self, path: Option<PathBuf>) {
+        self.cache.pinned = path;
+    }
+
+    /// Decode `paths` in order, replacing the decodes that didn't start yet
+    ///
+    /// Images being rescaled to the current scale aren't decoded again.
+    pub fn request(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut jobs = self.shared.lock();
+
+        let pending = &mut self.pending;
+        jobs.queue.retain(|job| match job {
+            Job::Decode(path) => {
+                pending.remove(path);
+                false
+            },
+            Job::Rescale(..) => true,
+        });
+
+        for path in paths {
+            let rescaling = self.rescaling.iter().any(|(p, s)| *p == path && *s == self.scale);
+            if !self.cache.contains(&path) && !rescaling && self.pending.insert(path.clone()) {
+                jobs.queue.push_back(Job::Decode(path));
+            }
+        }
+
+        drop(jobs);
+        self.shared.ready.notify_all();
+    }
+
+    /// Make a frame of the already decoded `original` of `path` for `scale`, before any decode
+    ///
+    /// Frames for the current scale are cached like decoded images, others are picked up with
+    /// [Self::take_rescaled].
+    pub fn rescale(&mut self, path: PathBuf, original: Arc<DynamicImage>, scale: Scale) {
+        if self.rescaling.iter().any(|(p, s)| *p == path && *s == scale) {
+            return;
+        }
+        self.rescaling.push((path.clone(), scale));
+
+        self.shared.lock().queue.push_front(Job::Rescale(path, original, scale));
+        self.shared.ready.notify_one();
+    }
+
+    /// Frame of `path` rescaled to `scale` by [Self::rescale], once it's done
+    pub fn take_rescaled(&mut self, path: &Path, scale: Scale) -> Option<Arc<Frame>> {
+        match self.rescaled.take() {
+            Some((p, frame)) if p == path && frame.scale == scale => Some(frame),
+            other => {
+                self.rescaled = other;
+                None
+            },
+        }
+    }
+
+    /// Cache the images decoded or rescaled since the last call, returns whether any arrived
+    pub fn poll(&mut self) -> bool {
+        let mut arrived = false;
+
+        while let Ok(done) = self.results.try_recv() {
+            match done {
+                Done::Decoded(path, decoded) => {
+                    self.pending.remove(&path);
+                    self.cache.insert(path, decoded);
+                },
+                Done::Rescaled(path, frame) => {
+                    self.rescaling.retain(|(p, s)| *p != path || *s != frame.scale);
+                    if frame.scale == self.scale {
+                        self.cache.insert(path, Some(frame));
+                    } else {
+                        self.rescaled = Some((path, frame));
+                    }
+                },
+            }
+            arrived = true;
+        }
+
+        arrived
+    }
+
+    /// Drop the cached image of `path`, e.g. after the file was moved away
+    pub fn forget(&mut self, path: &Path) {
+        self.cache.remove(path);
+        if self.rescaled.as_ref().is_some_and(|(p, _)| p == path) {
+            self.rescaled = None;
+        }
+    }
+
+    /// Shrink the next images to `scale`, the cached ones made for another scale are dropped
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+        *self.shared.scale.lock().unwrap_or_else(PoisonError::into_inner) = scale;
+        self.cache.retain(|frame| frame.as_ref().is_none_or(|f| f.scale == scale));
+    }
+}
+
+impl Frame {
+    /// Frame of `original`, shrunk to fit `scale` if needed
+    pub fn new(original: Arc<DynamicImage>, scale: Scale) -> Self {
+        let [w, h] = scale.bound;
+
+        let display = if original.width() > w || original.height() > h {
+            Arc::new(original.resize(w, h, filter_type(scale.filter)))
+        } else {
+            original.clone()
+        };
+
+        Self { original, display, scale }
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl Shared {
+    /// Workers never panic while holding the lock, a poisoned one is still consistent
+    fn lock(&self) -> MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Cache {
+    fn get(&mut self, path: &Path) -> Option<Decoded> {
+        self.clock += 1;
+        let (decoded, last_use) = self.images.get_mut(path)?;
+        *last_use = self.clock;
+
+        Some(decoded.clone())
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.images.contains_key(path)
+    }
+
+    fn insert(&mut self, path: PathBuf, decoded: Decoded) {
+        self.remove(&path);
+
+        self.clock += 1;
+        self.bytes += size_of(&decoded);
+        self.images.insert(path.clone(), (decoded, self.clock));
+
+        // the newest and the pinned entries are always kept, even when larger than the budget
+        while self.bytes > self.budget {
+            let oldest = self.images.iter()
+                .filter(|(p, _)| **p != path && Some(*p) != self.pinned.as_ref())
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(path, _)| path.clone());
+
+            match oldest {
+                Some(path) => self.remove(&path),
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some((decoded, _)) = self.images.remove(path) {
+            self.bytes -= size_of(&decoded);
+        }
+    }
+
+    fn retain(&mut self, keep: impl Fn(&Decoded) -> bool) {
+        let removed: Vec<PathBuf> = self.images.iter()
+            .filter(|(_, (decoded, _))| !keep(decoded))
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in removed {
+            self.remove(&path);
+        }
+    }
+}
+
+// -- private items
+fn worker(shared: &Shared, results: &mpsc::Sender<Done>) {
+    loop {
+        let job = {
+            let mut jobs = shared.lock();
+            loop {
+                if jobs.closed {
+                    return;
+                }
+                if let Some(job) = jobs.queue.pop_front() {
+                    break job;
+                }
+                jobs = shared.ready.wait(jobs).unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+
+        let done = match job {
+            Job::Decode(path) => {
+                let decoded = decode(&path).map(|img| {
+                    let scale = *shared.scale.lock().unwrap_or_else(PoisonError::into_inner);
+                    Arc::new(Frame::new(Arc::new(img), scale))
+                });
+                Done::Decoded(path, decoded)
+            },
+            Job::Rescale(path, original, scale) => {
+                Done::Rescaled(path, Arc::new(Frame::new(original, scale)))
+            },
+        };
+
+        if results.send(done).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decode by contents, the extension may be wrong, and turn upright
+fn decode(path: &Path) -> Option<DynamicImage> {
+    let img = image::io::Reader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(image::ImageError::from)
+        .and_then(|r| r.decode());
+
+    match img {
+        Ok(img) => Some(Orientation::read(path).apply(img)),
+        Err(e) => {
+            eprintln!("failed to open image at {path:?}: {e}");
+            None
+        },
+    }
+}
+
+/// Memory used by a decoded image, both versions
+fn size_of(decoded: &Decoded) -> usize {
+    decoded.as_ref().map_or(0, |frame| {
+        let display = if Arc::ptr_eq(&frame.original, &frame.display) {
+            0
+        } else {
+            frame.display.as_bytes().len()
+        };
+
+        frame.original.as_bytes().len() + display
+    })
+}
+
+fn filter_type(filter: ScaleFilter) -> FilterType {
+    match filter {
+        ScaleFilter::Nearest => FilterType::Nearest,
+        ScaleFilter::Triangle => FilterType::Triangle,
+        ScaleFilter::CatmullRom => FilterType::CatmullRom,
+        ScaleFilter::Gaussian => FilterType::Gaussian,
+        ScaleFilter::Lanczos3 => FilterType::Lanczos3,
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 300 bytes decoded
+    fn frame() -> Decoded {
+        let scale = Scale { bound: [100, 100], filter: ScaleFilter::Nearest };
+        Some(Arc::new(Frame::new(Arc::new(DynamicImage::new_rgb8(10, 10)), scale)))
+    }
+
+    fn cache(budget: usize) -> Cache {
+        Cache { images: HashMap::new(), bytes: 0, budget, clock: 0, pinned: None }
+    }
+
+    #[test]
+    fn rescales_in_the_background() {
+        let small = Scale { bound: [5, 5], filter: ScaleFilter::Nearest };
+        let detail = Scale { bound: [8, 8], ..small };
+        let original = Arc::new(DynamicImage::new_rgb8(10, 10));
+
+        let mut decoder = Decoder::new(CACHE_BYTES, small);
+        decoder.rescale("a".into(), original.clone(), small);
+        decoder.rescale("a".into(), original.clone(), detail);
+
+        let mut arrived = 0;
+        for _ in 0..500 {
+            if decoder.poll() {
+                arrived += 1;
+            }
+            if decoder.rescaling.is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(arrived > 0 && decoder.rescaling.is_empty());
+
+        // the current scale is cached, the other one is kept aside
+        let frame = decoder.get(Path::new("a")).flatten().unwrap();
+        assert!(Arc::ptr_eq(&frame.original, &original));
+        assert_eq!(frame.display.dimensions(), (5, 5));
+
+        assert!(decoder.take_rescaled(Path::new("b"), detail).is_none());
+        let frame = decoder.take_rescaled(Path::new("a"), detail).unwrap();
+        assert_eq!(frame.display.dimensions(), (8, 8));
+        assert!(decoder.take_rescaled(Path::new("a"), detail).is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = cache(700);
+        for name in ["a", "b", "c"] {
+            cache.insert(name.into(), frame());
+        }
+
+        assert!(!cache.contains(Path::new("a")));
+        assert!(cache.contains(Path::new("b")) && cache.contains(Path::new("c")));
+        assert_eq!(cache.bytes, 600);
+    }
+
+    #[test]
+    fn pinned_image_survives_a_batch() {
+        let mut cache = cache(700);
+        cache.pinned = Some("wanted".into());
+
+        // arrives first in the same poll as its neighbours
+        for name in ["wanted", "a", "b", "c"] {
+            cache.insert(name.into(), frame());
+        }
+
+        assert!(cache.get(Path::new("wanted")).is_some_and(|d| d.is_some()));
+        assert!(cache.contains(Path::new("c")));
+        assert!(cache.bytes <= 700);
+    }
+
+    #[test]
+    fn newest_is_kept_past_the_budget() {
+        let mut cache = cache(100);
+        cache.pinned = Some("wanted".into());
+        cache.insert("wanted".into(), frame());
+        cache.insert("a".into(), frame());
+
+        assert!(cache.contains(Path::new("wanted")) && cache.contains(Path::new("a")));
+
+        cache.insert("b".into(), frame());
+        assert!(!cache.contains(Path::new("a")));
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,59 @@
+//! Sorting errors
+//!
+//! Failures of the [SortQueue](crate::SortQueue), none of them leave the queue or the files in a
+//! broken state so the frontend can show them and carry on.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{fmt, io, path::PathBuf};
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+#[derive(Debug)]
+pub enum Error {
+    /// The folder to sort can't be opened, listed or written to
+    OpenFolder(PathBuf, io::Error),
+    /// An output folder can't be created
+    CreateDir(PathBuf, io::Error),
+    /// Every file is already sorted
+    EmptyQueue,
+    /// A file couldn't be moved, it is still at `from`
+    Move { from: PathBuf, to: PathBuf, error: io::Error },
+    /// A file couldn't be opened in an external program
+    Open(PathBuf, io::Error),
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Error {
+    /// Underlying io error, if any
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::OpenFolder(_, e) | Self::CreateDir(_, e) | Self::Open(_, e) => Some(e),
+            Self::Move { error, .. } => Some(error),
+            Self::EmptyQueue => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OpenFolder(p, e) => write!(f, "cannot open folder {}: {e}", p.display()),
+            Self::CreateDir(p, e) => write!(f, "cannot create folder {}: {e}", p.display()),
+            Self::EmptyQueue => write!(f, "no files left to sort"),
+            Self::Move { from, to, error } => {
+                write!(f, "cannot move {} to {}: {error}", from.display(), to.display())
+            },
+            Self::Open(p, e) => write!(f, "cannot open {}: {e}", p.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.io_error().map(|e| e as _)
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,382 @@
+//! File operations
+//!
+//! Moving files between folders, possibly on different devices, without ever losing data
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    fs::{self, File, FileTimes, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::CollisionPolicy;
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+const BUF_SIZE: usize = 64 * 1024;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// How a file was moved by [move_file]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveMethod {
+    /// Renamed in place, source and destination are on the same filesystem
+    Renamed,
+    /// Copied, verified and then removed from the source
+    Copied,
+}
+
+/// Where a file should go according to a [CollisionPolicy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Nothing exists at this path, the file can be moved there
+    Free(PathBuf),
+    /// A file with the same contents already exists at this path
+    Identical(PathBuf),
+    /// A different file exists at this path and the policy is to ask the user
+    Conflict(PathBuf),
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+/// Pick the final destination of `source` when moved to `destination`
+pub fn resolve_collision(
+    source: &Path,
+    destination: &Path,
+    policy: CollisionPolicy
+) -> io::Result<Resolution> {
+    if !destination.exists() {
+        return Ok(Resolution::Free(destination.to_path_buf()));
+    }
+
+    Ok(match policy {
+        CollisionPolicy::Suffix => Resolution::Free(numbered_path(destination)),
+
+        CollisionPolicy::Hash => {
+            let hash = hash_file_hex(source)?;
+            let hashed = suffixed_path(destination, &hash[..8]);
+
+            if !hashed.exists() {
+                Resolution::Free(hashed)
+            } else if same_contents(source, &hashed)? {
+                Resolution::Identical(hashed)
+            } else {
+                Resolution::Free(numbered_path(&hashed))
+            }
+        },
+
+        CollisionPolicy::SkipIdentical => {
+            if same_contents(source, destination)? {
+                Resolution::Identical(destination.to_path_buf())
+            } else {
+                Resolution::Free(numbered_path(destination))
+            }
+        },
+
+        CollisionPolicy::Prompt => Resolution::Conflict(destination.to_path_buf()),
+    })
+}
+
+/// First free `<stem>_<n>.<ext>` path next to `path`
+pub fn numbered_path(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| suffixed_path(path, &n.to_string()))
+        .find(|p| !p.exists())
+        .expect("ran out of numbered file names")
+}
+
+/// Whether both files have the same contents
+pub fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    Ok(hash_file(a)? == hash_file(b)?)
+}
+
+/// Move `source` to `destination`, failing if the destination exists
+///
+/// Tries a rename first and falls back to [copy_verified] when the destination is on another
+/// device, the source is only removed once the copy is known to be intact.
+pub fn move_file(source: &Path, destination: &Path) -> io::Result<MoveMethod> {
+    match rename_no_clobber(source, destination) {
+        Ok(()) => return Ok(MoveMethod::Renamed),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {},
+        Err(e) => return Err(e),
+    }
+
+    move_by_copy(source, destination)
+}
+
+/// Fallback of [move_file] between devices, [copy_verified] then remove the source
+///
+/// If the source can't be removed the copy is deleted again, so a failed move never leaves the
+/// file in both places.
+pub fn move_by_copy(source: &Path, destination: &Path) -> io::Result<MoveMethod> {
+    copy_verified(source, destination)?;
+
+    if let Err(e) = fs::remove_file(source) {
+        let _ = fs::remove_file(destination);
+        return Err(e);
+    }
+
+    Ok(MoveMethod::Copied)
+}
+
+/// Copy `source` to `destination`, checking the result before it becomes visible
+///
+/// The data is written to [partial_path] first, checked against the size and SHA-256 of the source
+/// and then renamed to the destination, which must not exist. Permissions and modification time
+/// are preserved.
+pub fn copy_verified(source: &Path, destination: &Path) -> io::Result<()> {
+    let partial = partial_path(destination);
+
+    let res = (|| {
+        let mut src = File::open(source)?;
+        let src_meta = src.metadata()?;
+
+        let mut dst = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&partial)?;
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; BUF_SIZE];
+        loop {
+            let n = src.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            dst.write_all(&buf[..n])?;
+        }
+
+        dst.set_permissions(src_meta.permissions())?;
+        let mut times = FileTimes::new().set_modified(src_meta.modified()?);
+        if let Ok(accessed) = src_meta.accessed() {
+            times = times.set_accessed(accessed);
+        }
+        dst.set_times(times)?;
+        dst.sync_all()?;
+        drop(dst);
+
+        verify(&partial, src_meta.len(), &hasher.finalize())
+            .map_err(|e| io::Error::other(format!("copy of {source:?} {e}")))?;
+
+        rename_no_clobber(&partial, destination)
+    })();
+
+    if res.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+
+    res
+}
+
+/// Temporary path a copy is written to before it is moved to `destination`
+pub fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(destination.file_name().unwrap_or_default());
+    name.push(".partial");
+    destination.with_file_name(name)
+}
+
+/// SHA-256 of the contents of a file
+pub fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut f = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; BUF_SIZE];
+
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+/// [hash_file] as a lowercase hex string
+pub fn hash_file_hex(path: &Path) -> io::Result<String> {
+    Ok(hash_file(path)?.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+// -- private items
+/// Rename `from` to `to`, failing with [io::ErrorKind::AlreadyExists] instead of replacing a file
+///
+/// A plain rename replaces the destination, so the file is hard linked and the old name removed.
+/// On filesystems without hard links it falls back to checking the destination before renaming.
+fn rename_no_clobber(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => {},
+        Err(e) if matches!(e.kind(), io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied) => {
+            if to.exists() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{to:?} already exists")));
+            }
+            return fs::rename(from, to);
+        },
+        Err(e) => return Err(e),
+    }
+
+    if let Err(e) = fs::remove_file(from) {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Check the file at `path` has `len` bytes and the SHA-256 `hash`
+fn verify(path: &Path, len: u64, hash: &[u8]) -> io::Result<()> {
+    let actual = fs::metadata(path)?.len();
+    if actual != len {
+        return Err(io::Error::other(format!("is truncated: {actual} of {len} bytes")));
+    }
+
+    if hash_file(path)? != hash {
+        return Err(io::Error::other("does not match the original"));
+    }
+
+    Ok(())
+}
+
+/// `<stem>_<suffix>.<ext>`
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("_");
+    name.push(suffix);
+
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+
+    path.with_file_name(name)
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, time::{Duration, SystemTime}};
+
+    use super::*;
+
+    #[test]
+    fn copy_keeps_permissions_and_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, destination) = (dir.path().join("a.png"), dir.path().join("b.png"));
+        fs::write(&source, b"image data").unwrap();
+
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let file = File::options().write(true).open(&source).unwrap();
+        file.set_modified(modified).unwrap();
+        file.set_permissions(fs::Permissions::from_mode(0o640)).unwrap();
+        drop(file);
+
+        copy_verified(&source, &destination).unwrap();
+
+        let meta = fs::metadata(&destination).unwrap();
+        assert_eq!(fs::read(&destination).unwrap(), b"image data");
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        assert_eq!(meta.modified().unwrap(), modified);
+        assert!(source.exists());
+        assert!(!partial_path(&destination).exists());
+    }
+
+    #[test]
+    fn failed_copy_removes_the_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, destination) = (dir.path().join("a.png"), dir.path().join("b.png"));
+        fs::write(&source, b"image data").unwrap();
+        // a folder that isn't empty can't be replaced by the copy
+        fs::create_dir(&destination).unwrap();
+        fs::write(destination.join("c.png"), b"").unwrap();
+
+        assert!(copy_verified(&source, &destination).is_err());
+        assert!(!partial_path(&destination).exists());
+        assert!(destination.join("c.png").exists());
+
+        assert!(copy_verified(&dir.path().join("missing.png"), &dir.path().join("d.png")).is_err());
+        assert!(!partial_path(&dir.path().join("d.png")).exists());
+    }
+
+    #[test]
+    fn verify_catches_truncated_and_changed_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.png");
+        fs::write(&path, b"image data").unwrap();
+        let hash = hash_file(&path).unwrap();
+
+        assert!(verify(&path, 10, &hash).is_ok());
+
+        let truncated = verify(&path, 11, &hash).unwrap_err();
+        assert!(truncated.to_string().contains("truncated: 10 of 11 bytes"), "{truncated}");
+
+        fs::write(&path, b"image_data").unwrap();
+        let changed = verify(&path, 10, &hash).unwrap_err();
+        assert!(changed.to_string().contains("does not match"), "{changed}");
+    }
+
+    #[test]
+    fn moves_never_replace_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, destination) = (dir.path().join("a.png"), dir.path().join("b.png"));
+        fs::write(&source, b"image data").unwrap();
+        fs::write(&destination, b"other image").unwrap();
+
+        let e = move_file(&source, &destination).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        let e = move_by_copy(&source, &destination).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+
+        assert_eq!(fs::read(&source).unwrap(), b"image data");
+        assert_eq!(fs::read(&destination).unwrap(), b"other image");
+        assert!(!partial_path(&destination).exists());
+
+        let moved = dir.path().join("c.png");
+        assert_eq!(move_file(&source, &moved).unwrap(), MoveMethod::Renamed);
+        assert!(!source.exists());
+        assert_eq!(fs::read(&moved).unwrap(), b"image data");
+    }
+
+    #[test]
+    fn move_by_copy_removes_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, destination) = (dir.path().join("a.png"), dir.path().join("b.png"));
+        fs::write(&source, b"image data").unwrap();
+
+        assert_eq!(move_by_copy(&source, &destination).unwrap(), MoveMethod::Copied);
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"image data");
+    }
+
+    #[test]
+    fn move_by_copy_keeps_a_single_file_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("read-only");
+        fs::create_dir(&folder).unwrap();
+        let (source, destination) = (folder.join("a.png"), dir.path().join("b.png"));
+        fs::write(&source, b"image data").unwrap();
+
+        // the source can be copied but not removed, unless permissions are ignored (root)
+        fs::set_permissions(&folder, fs::Permissions::from_mode(0o555)).unwrap();
+        let ignored = File::create(folder.join("probe")).is_ok();
+
+        if !ignored {
+            assert!(move_by_copy(&source, &destination).is_err());
+            assert!(source.exists());
+            assert!(!destination.exists());
+        }
+
+        fs::set_permissions(&folder, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
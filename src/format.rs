@@ -0,0 +1,196 @@
+//! Image formats
+//!
+//! Files are recognized by their first bytes, the extension (in any case) is only used for empty
+//! files. A file with an image extension and other contents is not an image.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Bytes read to detect the format, enough for every signature and the BMP header size
+const MAGIC_LEN: usize = 18;
+
+/// Sizes of the BMP info headers, from the OS/2 one to version 5
+const BMP_HEADER_SIZES: [u32; 6] = [12, 40, 52, 56, 108, 124];
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Format of an image file, the ones the viewer can decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Ico,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl ImageFormat {
+    pub const ALL: [Self; 7] = [
+        Self::Png, Self::Jpeg, Self::Gif, Self::Webp, Self::Bmp, Self::Tiff, Self::Ico,
+    ];
+
+    /// Extensions of the format, lowercase
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Png => &["png"],
+            Self::Jpeg => &["jpg", "jpeg", "jpe", "jfif"],
+            Self::Gif => &["gif"],
+            Self::Webp => &["webp"],
+            Self::Bmp => &["bmp", "dib"],
+            Self::Tiff => &["tif", "tiff"],
+            Self::Ico => &["ico"],
+        }
+    }
+
+    /// Format of a file extension, ignoring case
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let ext = ext.to_ascii_lowercase();
+        Self::ALL.into_iter().find(|f| f.extensions().contains(&ext.as_str()))
+    }
+
+    /// Format of a file starting with `bytes`
+    ///
+    /// "BM" is a common start for text as well, BMPs also need a known info header size.
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        Some(match bytes {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Self::Png,
+            [0xff, 0xd8, 0xff, ..] => Self::Jpeg,
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Self::Gif,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Self::Webp,
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => Self::Tiff,
+            [0, 0, 1, 0, ..] => Self::Ico,
+            [b'B', b'M', _, _, _, _, _, _, _, _, _, _, _, _, a, b, c, d, ..]
+                if BMP_HEADER_SIZES.contains(&u32::from_le_bytes([*a, *b, *c, *d])) => Self::Bmp,
+            _ => return None,
+        })
+    }
+
+    /// Format of the file at `path` from its contents, or from its extension if it's empty
+    pub fn detect(path: &Path) -> io::Result<Option<Self>> {
+        let mut magic = Vec::with_capacity(MAGIC_LEN);
+        File::open(path)?.take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+
+        if !magic.is_empty() {
+            return Ok(Self::from_magic(&magic));
+        }
+
+        Ok(path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_extension))
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // same names as in the config
+        f.write_str(match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpeg",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+            Self::Bmp => "bmp",
+            Self::Tiff => "tiff",
+            Self::Ico => "ico",
+        })
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn bmp(header_size: u32) -> Vec<u8> {
+        [b"BM".as_slice(), &[0; 12], &header_size.to_le_bytes()].concat()
+    }
+
+    #[test]
+    fn signatures() {
+        let cases: [(&[u8], _); 9] = [
+            (b"\x89PNG\r\n\x1a\n", ImageFormat::Png),
+            (&[0xff, 0xd8, 0xff, 0xdb], ImageFormat::Jpeg),
+            (b"GIF87a", ImageFormat::Gif),
+            (b"GIF89a", ImageFormat::Gif),
+            (b"RIFF\x10\x00\x00\x00WEBPVP8 ", ImageFormat::Webp),
+            (b"II*\x00\x08\x00\x00\x00", ImageFormat::Tiff),
+            (b"MM\x00*\x00\x00\x00\x08", ImageFormat::Tiff),
+            (&[0, 0, 1, 0, 1, 0], ImageFormat::Ico),
+            (&bmp(124), ImageFormat::Bmp),
+        ];
+
+        for (bytes, format) in cases {
+            assert_eq!(ImageFormat::from_magic(bytes), Some(format), "{bytes:?}");
+        }
+    }
+
+    #[test]
+    fn lookalikes() {
+        let cases: [&[u8]; 8] = [
+            b"",
+            b"\x89PNG",
+            b"GIF88a",
+            b"RIFF\x10\x00\x00\x00WAVEfmt ",
+            b"MM*\x00",
+            &[0, 0, 2, 0],
+            // text starting with BM, and BMPs cut before the header size
+            b"BMI 22.5, weighed on monday",
+            b"BM\x00\x00",
+        ];
+
+        for bytes in cases {
+            assert_eq!(ImageFormat::from_magic(bytes), None, "{bytes:?}");
+        }
+
+        for size in BMP_HEADER_SIZES {
+            assert_eq!(ImageFormat::from_magic(&bmp(size)), Some(ImageFormat::Bmp));
+        }
+        assert_eq!(ImageFormat::from_magic(&bmp(41)), None);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(ImageFormat::from_extension("JPG"), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::from_extension("jfif"), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::from_extension("Dib"), Some(ImageFormat::Bmp));
+        assert_eq!(ImageFormat::from_extension("txt"), None);
+    }
+
+    #[test]
+    fn detect_uses_the_extension_of_empty_files_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            ImageFormat::detect(&path).unwrap()
+        };
+
+        assert_eq!(file("photo.txt", &[0xff, 0xd8, 0xff, 0xe0]), Some(ImageFormat::Jpeg));
+        assert_eq!(file("a.png", b"GIF89a"), Some(ImageFormat::Gif));
+        assert_eq!(file("empty.PNG", b""), Some(ImageFormat::Png));
+        assert_eq!(file("empty", b""), None);
+        // mislabeled
+        assert_eq!(file("notes.png", b"not an image"), None);
+
+        assert!(ImageFormat::detect(&dir.path().join("missing.png")).is_err());
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
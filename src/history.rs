@@ -0,0 +1,75 @@
+//! Action history
+//!
+//! Undo and redo stacks for the file moves done by the [SortQueue](crate::SortQueue)
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::path::PathBuf;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// A single file move
+#[derive(Debug, Clone)]
+pub struct Action {
+    /// Original location of the file
+    pub source: PathBuf,
+
+    /// Location the file was moved to
+    pub destination: PathBuf,
+
+    /// Position of the file in the queue before it was moved
+    pub index: usize,
+
+    /// Filename typed by the user for this file
+    pub typed_name: String,
+
+    /// Id of the move in the [Journal](crate::journal::Journal)
+    pub journal_id: u64,
+}
+
+/// Undo/redo stacks
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Action>,
+    redo: Vec<Action>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl History {
+    /// Record a new action, discarding anything that could be redone
+    pub fn push(&mut self, action: Action) {
+        self.redo.clear();
+        self.undo.push(action);
+    }
+
+    /// Take the most recent action to be undone
+    pub fn pop_undo(&mut self) -> Option<Action> {
+        self.undo.pop()
+    }
+
+    /// Take the most recently undone action to be redone
+    pub fn pop_redo(&mut self) -> Option<Action> {
+        self.redo.pop()
+    }
+
+    /// Record an action that was undone, so it can be redone later
+    pub fn push_undone(&mut self, action: Action) {
+        self.redo.push(action);
+    }
+
+    /// Record an action that was redone, keeping the rest of the redo stack
+    pub fn push_redone(&mut self, action: Action) {
+        self.undo.push(action);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
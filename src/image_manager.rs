@@ -1,234 +1,379 @@
-//! Manages and shows image files
+//! Shows the image files of the [SortQueue]
 
 /*--- Use ----------------------------------------------------------------------------------------*/
 
-use std::{collections::{HashSet, HashMap}, fs::ReadDir, path::{Path, PathBuf}, sync::Arc};
-use lazy_static::{lazy_static, __Deref};
+use std::{collections::HashMap, ops::Deref, path::{Path, PathBuf}, sync::Arc};
 
-use nannou::{image::DynamicImage, draw::primitive::Texture};
-use nannou::image;
+use nannou::{App, image::{self, DynamicImage, GenericImageView}, wgpu};
 
-use crate::*;
+use image_sorter::{
+    config::{CollisionPolicy, Config, ScaleFilter},
+    queue::{MoveOutcome, SortQueue},
+    viewport::Viewport,
+    Error,
+};
+
+use crate::decoder::{Decoded, Decoder, Frame, Scale, CACHE_BYTES, PREFETCH};
+use crate::PLACEHOLDER_BUF;
 
 /*--- Const --------------------------------------------------------------------------------------*/
 
-lazy_static!{
-    /// Supported file types
-    static ref ALLOWED_FILE_TYPES: HashSet<String> = vec![ "png", "jpg", "jpeg", "webp"]
-        .drain(..).map(|v| v.to_string()).collect();
-}
+/// Display sizes are rounded up to a multiple of this, so resizing the window doesn't rescale the
+/// image on every frame
+const SCALE_STEP: u32 = 256;
 
 /*--- Impl ---------------------------------------------------------------------------------------*/
 
 /// Image and file manager
+///
+/// Derefs to the [SortQueue], operations that change the current file go through the manager so
+/// the image follows them. Images are decoded in the background, the placeholder is shown until
+/// the current one is ready, and shrunk to the window size before they become a texture. Zooming
+/// past the display size uses a texture of the original instead. Rescales run in the background as
+/// well, the image is shown as it is until they finish.
 pub struct ImageManager {
-    pub image_index: usize,
-    pub images: Vec<String>,
-
-    total_file_count: usize,
-    dir: PathBuf,
-    default_path: PathBuf,
-    image_current: DynamicImage,
-    image_current_texture: Option<(Arc<wgpu::Texture>, String)>,
+    queue: SortQueue,
+    decoder: Decoder,
+
+    /// File whose image should be shown, `None` once the queue is empty
+    wanted: Option<PathBuf>,
+    /// The wanted image is still being decoded
+    loading: bool,
+    image_current: Arc<Frame>,
+    /// Bumped whenever [Self::image_current] changes, the texture is rebuilt when it differs
+    image_generation: u64,
+    image_current_texture: Option<(Arc<wgpu::Texture>, u64)>,
+    /// Original resolution, only while zoomed in past the display size, with the original it's of
+    image_detail_texture: Option<(Arc<wgpu::Texture>, Arc<DynamicImage>)>,
+    placeholder: Arc<Frame>,
+    /// Scale of the images shown, follows the window size
+    scale: Scale,
+    /// File outside of the queue being shown instead of the current image
+    preview: Option<PathBuf>,
+    /// Zoom and pan of every image looked at, until it's moved
+    viewports: HashMap<PathBuf, Viewport>,
 }
 
 impl ImageManager {
 
     // pub const PLACEHOLDER_FILENAME: &'static str = "missing-image-placeholder.png";
 
-    pub fn new(images_path: &PathBuf, config: &Config) -> Self {
+    /// Open the queue of `images_path`, an empty folder shows the placeholder
+    pub fn new(images_path: &Path, config: &Config) -> Result<Self, Error> {
+        let queue = SortQueue::new(images_path, config)?;
+
+        // until the window size is known
+        let max = wgpu::Limits::default().max_texture_dimension_2d;
+        let scale = Scale { bound: [max, max], filter: config.scale_filter };
+        let placeholder = Arc::new(Frame::new(Arc::new(Self::placeholder()), scale));
+
+        let mut manager = Self {
+            queue,
+            decoder: Decoder::new(CACHE_BYTES, scale),
+            wanted: None,
+            loading: false,
+            image_current: placeholder.clone(),
+            image_generation: 0,
+            image_current_texture: None,
+            image_detail_texture: None,
+            placeholder,
+            scale,
+            preview: None,
+            viewports: HashMap::new(),
+        };
+        manager.reload_image();
+
+        Ok(manager)
+    }
 
-        let dir = images_path.clone();
-        println!("images path: {dir:?}");
+    /// Create the destination folder of every button and the default folder
+    pub fn create_output_dirs(&self, config: &Config) -> Result<(), Error> {
+        self.queue.create_output_dirs(config)
+    }
 
+    pub fn set_bake_orientation(&mut self, bake: bool) {
+        self.queue.set_bake_orientation(bake);
+    }
 
-        let images = Self::get_file_list(&dir);
+    pub fn reindex(&mut self, config: &Config) {
+        self.queue.reindex(config);
+    }
 
-        if images.len() == 0 {
-            // TODO: show error message on the interface? (would require proper errors for this)
-            eprintln!("no supported files in current directory: {dir:?}");
-            std::process::exit(1);
+    /// Filter used to shrink images from now on, the shown one is shrunk again
+    pub fn set_scale_filter(&mut self, filter: ScaleFilter) {
+        if filter != self.scale.filter {
+            self.apply_scale(Scale { filter, ..self.scale });
         }
+    }
 
-        println!("file count: {}", images.len());
+    pub fn next_image(&mut self) {
+        self.queue.next_image();
+        self.reload_image();
+    }
 
+    pub fn prev_image(&mut self) {
+        self.queue.prev_image();
+        self.reload_image();
+    }
 
-        let i = config.buttons.iter()
-            .map(|(_, b_cfg)| b_cfg.path.as_str())
-            .chain(std::iter::once(config.default_folder.as_str()));
+    pub fn seek_to_image(&mut self, pos: usize) {
+        self.queue.seek_to_image(pos);
+        self.reload_image();
+    }
 
-        for path in i {
-            std::fs::create_dir_all(dir.join(path))
-                .expect(format!("failed to create output directory {path}").as_str());
+    pub fn move_current(
+        &mut self,
+        category: &str,
+        new_name: &str,
+        collision: CollisionPolicy
+    ) -> Result<MoveOutcome, Error> {
+        let source = self.queue.get_current_path();
+        let res = self.queue.move_current(category, new_name, collision);
+
+        if let (Ok(MoveOutcome::Moved(_)), Some(source)) = (&res, source) {
+            self.decoder.forget(&source);
+            self.viewports.remove(&source);
         }
 
-        let image_path = dir.join(&images[0]);
-        println!("first image: {image_path:?}");
-        let image_current = Self::open_image_or_default(image_path);
-        //wgpu::Texture::from_path(app, image_path).unwrap();
-
-        Self {
-            default_path: PathBuf::from(&config.default_folder),
-            image_current_texture: None,
-            image_index: 0,
-            total_file_count: images.len(),
-            image_current,
-            dir,
-            images,
-        }
+        self.reload_image();
+        res
     }
 
-    pub fn get_total_files(&self) -> usize {
-        self.total_file_count
+    pub fn undo(&mut self) -> Result<Option<String>, Error> {
+        let res = self.queue.undo();
+        self.reload_image();
+        res
     }
 
-    pub fn get_images_len(&self) -> usize {
-        self.images.len()
+    pub fn redo(&mut self) -> Result<(), Error> {
+        let res = self.queue.redo();
+        self.reload_image();
+        res
     }
 
-    pub fn get_current_pos(&self) -> usize {
-        self.image_index
+    /// Show the current file of the queue and decode its neighbours ahead
+    pub fn reload_image(&mut self) {
+        self.preview = None;
+        self.show(self.queue.get_current_path());
+        self.prefetch();
     }
 
-    pub fn get_current_path(&self) -> PathBuf {
-        self.dir.join(self.get_current_filename())
+    /// Show `path` instead of the current image until the queue is navigated
+    pub fn show_preview(&mut self, path: &Path) {
+        self.show(Some(path.to_path_buf()));
+        self.preview = Some(path.to_path_buf());
+        self.decoder.request([path.to_path_buf()]);
     }
 
-    pub fn get_current_filename<'s>(&'s self) -> &'s str {
-        &self.images[self.image_index]
+    pub fn preview(&self) -> Option<&Path> {
+        self.preview.as_deref()
     }
 
-    pub fn next_image(&mut self) {
-        let max = self.images.len() - 1;
-        self.image_index += if self.image_index >= max { 0 } else { 1 };
-        self.reload_image();
+    /// Whether the placeholder is shown while the image is still being decoded
+    pub fn is_loading(&self) -> bool {
+        self.loading
     }
 
-    pub fn prev_image(&mut self) {
-        self.image_index -= if self.image_index == 0 { 0 } else { 1 };
-        self.reload_image();
+    /// File being shown, the current one or the preview
+    pub fn shown(&self) -> Option<&Path> {
+        self.wanted.as_deref()
     }
 
-    pub fn seek_to_image(&mut self, pos:usize) {
-        let max = self.images.len() - 1;
+    /// Decoded shown image, `None` while it's loading or if it couldn't be decoded
+    pub fn image(&self) -> Option<&DynamicImage> {
+        let placeholder = Arc::ptr_eq(&self.image_current.original, &self.placeholder.original);
+        (!self.loading && !placeholder).then(|| self.image_current.original.as_ref())
+    }
 
-        self.image_index = if pos >= max { max } else { pos };
+    /// Size of the shown image in pixels, before it's shrunk for display
+    pub fn image_size(&self) -> [f32; 2] {
+        let (w, h) = self.image_current.original.dimensions();
+        [w as f32, h as f32]
+    }
 
-        self.reload_image()
+    /// Zoom and pan of the shown image
+    pub fn viewport(&self) -> Viewport {
+        self.wanted.as_ref()
+            .and_then(|p| self.viewports.get(p))
+            .copied()
+            .unwrap_or_default()
     }
 
-    pub fn reload_image(&mut self) {
-        let image_path = self.get_current_filename();
+    /// Zoom and pan of the shown image, `None` while only the placeholder is left
+    pub fn viewport_mut(&mut self) -> Option<&mut Viewport> {
+        let path = self.wanted.clone()?;
+        Some(self.viewports.entry(path).or_default())
+    }
 
-        self.image_current = Self::open_image_or_default(self.dir.join(image_path));
+    /// get the Texture from the current image, `None` until [Self::update_texture] is called
+    pub fn get_texture(&self) -> Option<Arc<wgpu::Texture>> {
+        self.image_detail_texture.as_ref().map(|(t, _)| t)
+            .or(self.image_current_texture.as_ref().map(|(t, _)| t))
+            .cloned()
     }
 
-    /// Path is prepended with no extra tokens so save can handle both separate and regular save
+    /// Pick up the images decoded in the background and rebuild the texture if the image changed
+    ///
+    /// A library scan started by [SortQueue::reindex] is picked up here as well.
     ///
-    /// Category is essentially the destination folder
-    pub fn move_current(&mut self, category: &str, new_name: &str) {
+    /// Images are shrunk to the window size, never past the texture size limit of the GPU. When
+    /// the [Viewport] on a `canvas` sized area needs more detail than that, the texture is made
+    /// from the original, still within the limit.
+    pub fn update_texture(&mut self, app: &App, canvas: [f32; 2]) {
+        if let Some(scale) = self.window_scale(app) {
+            if scale != self.scale {
+                self.apply_scale(scale);
+            }
+        }
 
-        // TODO: implement act history
-        // TODO: make category a enum? it's the destination folder
+        self.queue.poll_reindex();
 
-        let f = &self.images[self.image_index];
-        let source_f = self.dir.join(f);
+        if self.decoder.poll() {
+            if let Some(decoded) = self.wanted.clone().and_then(|p| self.decoder.get(&p)) {
+                // rescaled to the current scale, replacing the one shown until then
+                let rescaled = decoded.as_ref()
+                    .is_some_and(|f| f.scale == self.scale && f.scale != self.image_current.scale);
 
-        // remove spaces from filename
-        let f_str: std::string::String =
-            f.chars().map(|c| if c == ' ' { '_' } else { c }).collect();
+                if self.loading || rescaled {
+                    self.loading = false;
+                    self.set_image(decoded);
+                }
+            }
+        }
 
-        let output_path = self
-            .dir
-            .join(category)
-            .join(format!("{}__{}", new_name.trim_end_matches("--"), f_str));
+        let outdated = self.image_current_texture.as_ref()
+            .is_none_or(|(_, generation)| *generation != self.image_generation);
 
-        println!("moving file: {source_f:?} -> {output_path:?}");
+        if outdated {
+            self.convert_img(app);
+        }
 
-        std::fs::copy(&source_f, &output_path).expect("failed to save file");
+        self.update_detail(app, canvas);
+    }
 
-        std::fs::remove_file(&source_f).expect("failed to  file");
+    // -- private items
+    /// Show the image of `path` if it's decoded, the placeholder otherwise
+    fn show(&mut self, path: Option<PathBuf>) {
+        let cached = path.as_deref().and_then(|p| self.decoder.get(p));
+        self.loading = path.is_some() && cached.is_none();
+        self.decoder.pin(path.clone());
+        self.wanted = path;
+        self.set_image(cached.flatten());
+    }
 
-        self.images.remove(self.image_index);
-        self.reload_image();
-        // new_name.clear()
+    fn set_image(&mut self, decoded: Decoded) {
+        self.image_current = decoded.unwrap_or_else(|| self.placeholder.clone());
+        self.image_generation += 1;
+        self.rescale_current();
     }
 
+    /// Rescale the shown image in the background if it was made for another scale
+    fn rescale_current(&mut self) {
+        let frame = &self.image_current;
+        if frame.scale == self.scale {
+            return;
+        }
 
-    /// get the Texture from the current image
-    ///
-    /// Panics if called before update texture
-    // TODO: load default texture on new
-    pub fn get_texture(&self) -> Arc<wgpu::Texture> {
-        self.image_current_texture.clone().unwrap().0
+        if Arc::ptr_eq(&frame.original, &self.placeholder.original) {
+            self.image_current = self.placeholder.clone();
+            self.image_generation += 1;
+        } else if let Some(path) = self.wanted.clone() {
+            self.decoder.rescale(path, frame.original.clone(), self.scale);
+        }
     }
 
-    pub fn update_texture(&mut self, app: &App) {
-        if let Some((_, name)) = &mut self.image_current_texture {
-            if *name != self.images[self.image_index] {
-                self.convert_img(app);
-            }
-        } else {
-            self.convert_img(app);
-        }
+    /// Scale for the current window size, `None` if there is no window
+    fn window_scale(&self, app: &App) -> Option<Scale> {
+        let window = app.window(app.window_id())?;
+        let (w, h) = window.inner_size_pixels();
+        let max = window.device().limits().max_texture_dimension_2d;
+
+        let round = |v: u32| (v / SCALE_STEP + 1).saturating_mul(SCALE_STEP).min(max);
+        Some(Scale { bound: [round(w), round(h)], filter: self.scale.filter })
     }
 
-    // -- private items
-    fn open_image_or_default<P>(path: P) -> DynamicImage where P: AsRef<Path> + std::fmt::Debug {
-        match image::open(path.as_ref()) {
-            Ok(img) => {
-                println!("opened image at: {path:?}");
-                img
-            }
-            Err(_e) => {
-                eprintln!("failed to open image at: {path:?}");
-                image::load_from_memory_with_format(
-                    PLACEHOLDER_BUF.as_ref(),
-                    image::ImageFormat::Bmp
-                ).unwrap()
-            }
-        }
+    /// Rescale the shown image and decode the neighbours again at the new scale
+    fn apply_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+        self.decoder.set_scale(scale);
+
+        // small enough to rescale right away
+        self.placeholder = Arc::new(Frame::new(self.placeholder.original.clone(), scale));
+        self.rescale_current();
+        self.prefetch();
     }
 
+    /// Decode the wanted image and then its neighbours in the queue, closest first
+    fn prefetch(&mut self) {
+        let pos = self.queue.get_current_pos();
+        let neighbours = (1..=PREFETCH)
+            .flat_map(|d| [pos.checked_add(d), pos.checked_sub(d)])
+            .flatten()
+            .filter_map(|i| self.queue.images.get(i))
+            .map(|f| self.queue.dir().join(f));
+
+        let paths: Vec<PathBuf> = self.wanted.clone().into_iter().chain(neighbours).collect();
+        self.decoder.request(paths);
+    }
 
-    fn get_file_list<P>(dir: P) -> Vec<String> where P: AsRef<Path> {
-        // filter files in the directory that match certain criteria
-        std::fs::read_dir(&dir)
-            .unwrap()
-            .filter_map(|i| {
-                if let Ok(it) = i {
-                    if let Ok(ft) = it.file_type() {
-                        if ft.is_file() {
-                            let filename = it.file_name().into_string().unwrap();
-                            if let Some(extension) = filename.split('.').last() {
-                                if ALLOWED_FILE_TYPES.contains(extension) {
-                                    Some(filename)
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-                })
-            .collect()
+    fn placeholder() -> DynamicImage {
+        image::load_from_memory_with_format(PLACEHOLDER_BUF.as_ref(), image::ImageFormat::Bmp)
+            .expect("the placeholder image is valid")
+    }
+
+    /// Build or drop the original resolution texture following the zoom
+    ///
+    /// The original is shrunk to the texture size limit in the background, the display texture is
+    /// shown until then.
+    fn update_detail(&mut self, app: &App, canvas: [f32; 2]) {
+        let frame = self.image_current.clone();
+        let factor = self.viewport().zoom.factor(self.image_size(), canvas);
+        let shrunk = frame.display.width() as f32 / frame.original.width().max(1) as f32;
+
+        if Arc::ptr_eq(&frame.original, &frame.display) || factor <= shrunk {
+            self.image_detail_texture = None;
+            return;
+        }
+
+        let current = self.image_detail_texture.as_ref()
+            .is_some_and(|(_, original)| Arc::ptr_eq(original, &frame.original));
+        if current {
+            return;
+        }
+        self.image_detail_texture = None;
+
+        let (Some(path), Some(window)) = (self.wanted.clone(), app.window(app.window_id())) else {
+            return;
+        };
+        let max = window.device().limits().max_texture_dimension_2d;
+        let scale = Scale { bound: [max, max], ..self.scale };
+
+        match self.decoder.take_rescaled(&path, scale) {
+            Some(detail) if Arc::ptr_eq(&detail.original, &frame.original) => {
+                self.image_detail_texture = Some((
+                    Arc::new(wgpu::Texture::from_image(app, &detail.display)),
+                    frame.original.clone(),
+                ));
+            },
+            _ => self.decoder.rescale(path, frame.original.clone(), scale),
+        }
     }
 
     fn convert_img(&mut self, app: &App) {
         self.image_current_texture =
             Some((
-                Arc::new(wgpu::Texture::from_image(app, &self.image_current)),
-                self.images[self.image_index].clone()
+                Arc::new(wgpu::Texture::from_image(app, &self.image_current.display)),
+                self.image_generation
             ));
     }
 }
 
+impl Deref for ImageManager {
+    type Target = SortQueue;
+
+    fn deref(&self) -> &Self::Target {
+        &self.queue
+    }
+}
+
 /*--------------------------------------------- EOF ----------------------------------------------*/
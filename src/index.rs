@@ -0,0 +1,313 @@
+//! Index of the sorted library
+//!
+//! Keeps track of every file in the destination folders together with the category and tags parsed
+//! from its [SortedName], so the library can be searched without listing it again. The index is a
+//! JSON file in the sorted folder, files are only hashed again when their size or mtime change.
+//!
+//! Moves are recorded without hashing the file, the hash is filled in by the next [scan], which
+//! runs on its own thread in the window.
+//!
+//! [scan]: LibraryIndex::scan
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    data_store::{DataStore, DataStoreError},
+    file_store,
+    sorted_name::SortedName,
+};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+pub const INDEX_FILE_NAME: &str = ".image-sorter.index.json";
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// A sorted file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Relative to the sorted folder
+    pub path: PathBuf,
+    /// Empty if the name could not be parsed
+    pub category: String,
+    pub tags: Vec<String>,
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub mtime: u64,
+    /// SHA-256 of the contents, lowercase hex, empty until the file is scanned
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// Contents of the index file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IndexData {
+    /// Folders that are indexed, relative to the sorted folder
+    folders: BTreeSet<PathBuf>,
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+/// Counts of what [LibraryIndex::scan] did
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanStats {
+    pub unchanged: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+/// Persistent index of the destination folders
+#[derive(Clone)]
+pub struct LibraryIndex {
+    /// Sorted folder, entry paths are relative to it
+    root: PathBuf,
+    store: DataStore<IndexData>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl LibraryIndex {
+    /// Load the index of `root`, starting an empty one if it doesn't exist yet
+    pub fn open(root: &Path) -> Result<Self, DataStoreError> {
+        let path = root.join(INDEX_FILE_NAME);
+
+        let store = if path.exists() {
+            DataStore::new_from_path(&path)?
+        } else {
+            DataStore::new_from_data(&path, IndexData::default())
+        };
+
+        Ok(Self { root: root.to_path_buf(), store })
+    }
+
+    /// Empty index of `root`, replacing the index file on the next save
+    pub fn empty(root: &Path) -> Self {
+        let store = DataStore::new_from_data(&root.join(INDEX_FILE_NAME), IndexData::default());
+        Self { root: root.to_path_buf(), store }
+    }
+
+    /// Destination folders of `config`: the button paths, the default folder and the rule paths
+    pub fn folders(config: &Config) -> BTreeSet<PathBuf> {
+        config.buttons.values()
+            .map(|b_cfg| PathBuf::from(&b_cfg.path))
+            .chain(std::iter::once(PathBuf::from(&config.default_folder)))
+            .chain(config.rules.iter().map(|r| PathBuf::from(&r.path)))
+            .collect()
+    }
+
+    /// Bring the index up to date with the contents of `folders`
+    ///
+    /// Only the files directly inside each folder are indexed, entries outside of `folders` or
+    /// whose file is gone are dropped. Files that changed or were never hashed are hashed.
+    pub fn scan(&mut self, folders: BTreeSet<PathBuf>) -> io::Result<ScanStats> {
+        let mut stats = ScanStats::default();
+        let mut seen = BTreeSet::new();
+
+        for folder in &folders {
+            let read_dir = match fs::read_dir(self.root.join(folder)) {
+                Ok(r) => r,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for dir_entry in read_dir {
+                let dir_entry = dir_entry?;
+                // sorted names are always valid unicode, the index can't store anything else
+                if !dir_entry.file_type()?.is_file() || dir_entry.file_name().to_str().is_none() {
+                    continue;
+                }
+
+                let rel = folder.join(dir_entry.file_name());
+                if self.refresh(&rel, true)? {
+                    stats.updated += 1;
+                } else {
+                    stats.unchanged += 1;
+                }
+                seen.insert(rel);
+            }
+        }
+
+        let before = self.store.entries.len();
+        self.store.entries.retain(|path, _| seen.contains(path));
+        stats.removed = before - self.store.entries.len();
+        self.store.folders = folders;
+
+        Ok(stats)
+    }
+
+    /// Update the index after a file was moved from `source` to `destination`
+    ///
+    /// Paths are absolute, files outside the indexed folders are ignored. The file isn't read, its
+    /// hash is left for the next [LibraryIndex::scan].
+    pub fn record_move(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        if let Some(rel) = self.relative(source) {
+            self.store.entries.remove(&rel);
+        }
+
+        if let Some(rel) = self.relative(destination) {
+            let indexed = rel.parent().is_some_and(|p| self.store.folders.contains(p))
+                && rel.to_str().is_some();
+            if indexed {
+                self.refresh(&rel, false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the index file, no backup is kept since a scan rebuilds it
+    pub fn save(&mut self) -> Result<(), DataStoreError> {
+        self.store.save_without_backup()
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.store.entries.values()
+    }
+
+    // -- private items
+    /// Index the file at `rel`, hashing it if `hash` is set, returns whether the entry changed
+    fn refresh(&mut self, rel: &Path, hash: bool) -> io::Result<bool> {
+        let path = self.root.join(rel);
+        let meta = fs::metadata(&path)?;
+        let size = meta.len();
+        let mtime = meta.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        if let Some(entry) = self.store.entries.get(rel) {
+            if entry.size == size && entry.mtime == mtime && (!hash || !entry.hash.is_empty()) {
+                return Ok(false);
+            }
+        }
+
+        let name: Option<SortedName> = rel.file_name()
+            .and_then(|f| f.to_str())
+            .and_then(|f| f.parse().ok());
+        let (category, tags) = match name {
+            Some(n) => (n.category().to_string(), n.tags().to_vec()),
+            None => (String::new(), vec![]),
+        };
+
+        let entry = Entry {
+            path: rel.to_path_buf(),
+            category,
+            tags,
+            size,
+            mtime,
+            hash: if hash { file_store::hash_file_hex(&path)? } else { String::new() },
+        };
+
+        self.store.entries.insert(rel.to_path_buf(), entry);
+        Ok(true)
+    }
+
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.root).ok().map(Path::to_path_buf)
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folders(names: &[&str]) -> BTreeSet<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn library(files: &[(&str, &[u8])]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn scan_indexes_the_folders() {
+        let dir = library(&[
+            ("output/anime--sticker__a.png", b"a"),
+            ("output/unsorted.png", b"b"),
+            ("output/nested/c.png", b"c"),
+            ("trash/__d.png", b"d"),
+            ("e.png", b"e"),
+        ]);
+        let mut index = LibraryIndex::empty(dir.path());
+
+        let stats = index.scan(folders(&["output", "missing"])).unwrap();
+        assert_eq!((stats.updated, stats.unchanged, stats.removed), (2, 0, 0));
+
+        let entry = index.entries().next().unwrap();
+        assert_eq!(entry.path, Path::new("output/anime--sticker__a.png"));
+        assert_eq!(entry.category, "anime");
+        assert_eq!(entry.tags, ["sticker"]);
+        assert_eq!(entry.hash, file_store::hash_file_hex(&dir.path().join(&entry.path)).unwrap());
+
+        let unsorted = index.entries().nth(1).unwrap();
+        assert_eq!((unsorted.category.as_str(), unsorted.tags.len()), ("", 0));
+
+        fs::remove_file(dir.path().join("output/unsorted.png")).unwrap();
+        let stats = index.scan(folders(&["output", "trash"])).unwrap();
+        assert_eq!((stats.updated, stats.unchanged, stats.removed), (1, 1, 1));
+
+        // entries of folders no longer indexed are dropped
+        let stats = index.scan(folders(&["trash"])).unwrap();
+        assert_eq!((stats.updated, stats.unchanged, stats.removed), (0, 1, 1));
+    }
+
+    #[test]
+    fn moves_are_hashed_by_the_next_scan() {
+        let dir = library(&[("a.png", b"a"), ("output/b.png", b"b")]);
+        let root = dir.path();
+        let mut index = LibraryIndex::empty(root);
+        index.scan(folders(&["output"])).unwrap();
+
+        fs::rename(root.join("a.png"), root.join("output/anime__a.png")).unwrap();
+        index.record_move(&root.join("a.png"), &root.join("output/anime__a.png")).unwrap();
+
+        let moved = index.entries().find(|e| e.category == "anime").unwrap();
+        assert_eq!((moved.size, moved.hash.as_str()), (1, ""));
+
+        let stats = index.scan(folders(&["output"])).unwrap();
+        assert_eq!((stats.updated, stats.unchanged), (1, 1));
+        assert!(index.entries().all(|e| !e.hash.is_empty()));
+
+        // out of the indexed folders
+        fs::create_dir(root.join("other")).unwrap();
+        fs::rename(root.join("output/b.png"), root.join("other/b.png")).unwrap();
+        index.record_move(&root.join("output/b.png"), &root.join("other/b.png")).unwrap();
+        assert_eq!(index.entries().count(), 1);
+    }
+
+    #[test]
+    fn saved_without_a_backup() {
+        let dir = library(&[("output/a.png", b"a")]);
+        let mut index = LibraryIndex::open(dir.path()).unwrap();
+        index.scan(folders(&["output"])).unwrap();
+        index.save().unwrap();
+        index.save().unwrap();
+
+        assert!(!dir.path().join(format!("{INDEX_FILE_NAME}.bak")).exists());
+        let reopened = LibraryIndex::open(dir.path()).unwrap();
+        assert_eq!(reopened.entries().collect::<Vec<_>>(), index.entries().collect::<Vec<_>>());
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,415 @@
+//! Write-ahead journal for file moves
+//!
+//! Every move is recorded before it starts and again once it finished, so a move interrupted by a
+//! crash can be completed or rolled back on the next start (see [Journal::recover]).
+//!
+//! The journal is a JSON lines file in the sorted folder. It is appended to while sorting and
+//! compacted by [Journal::recover] to the moves that can still be undone.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    collections::{BTreeMap, BTreeSet},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{file_store, orientation};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+pub const JOURNAL_FILE_NAME: &str = ".image-sorter.journal";
+
+/// Undoable moves kept when the journal is compacted, older ones can't be undone anymore
+pub const KEEP_UNDOABLE: usize = 1000;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// A single journal entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Record {
+    /// A move is about to start
+    Begin {
+        id: u64,
+        #[serde(with = "os_path")]
+        source: PathBuf,
+        #[serde(with = "os_path")]
+        destination: PathBuf,
+        /// Id of the move this one undoes
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reverts: Option<u64>,
+    },
+    /// The move finished, the file is only at the destination
+    Commit { id: u64 },
+    /// The move was abandoned, the file is only at the source
+    Rollback { id: u64 },
+}
+
+/// What [Journal::recover] did with an unfinished move
+#[derive(Debug, Clone)]
+pub enum Recovery {
+    /// The file was fully copied, the leftover source was removed
+    Completed { source: PathBuf, destination: PathBuf },
+    /// The copy was incomplete or never started, any partial copy was removed
+    RolledBack { source: PathBuf, destination: PathBuf },
+    /// Neither the source nor the destination exist anymore
+    Missing { source: PathBuf, destination: PathBuf },
+    /// Both files exist with different contents, both are kept and the move is rolled back
+    Differs { source: PathBuf, destination: PathBuf },
+}
+
+/// A completed move, see [Journal::undoable]
+#[derive(Debug, Clone)]
+pub struct Move {
+    pub id: u64,
+    pub source: PathBuf,
+    pub destination: PathBuf,
+}
+
+/// Append-only move journal
+pub struct Journal {
+    file: File,
+    next_id: u64,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Journal {
+    /// Open (or create) the journal in `dir`
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(JOURNAL_FILE_NAME);
+
+        let next_id = Self::read_records(&path)?
+            .iter()
+            .map(Record::id)
+            .max()
+            .map_or(0, |id| id + 1);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+
+        Ok(Self { file, next_id })
+    }
+
+    /// Record the intent to move `source` to `destination`
+    ///
+    /// `reverts` is the id of the move being undone, if any
+    pub fn begin(&mut self, source: &Path, destination: &Path, reverts: Option<u64>) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.append(&Record::Begin {
+            id,
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            reverts,
+        })?;
+
+        Ok(id)
+    }
+
+    pub fn commit(&mut self, id: u64) -> io::Result<()> {
+        self.append(&Record::Commit { id })
+    }
+
+    pub fn rollback(&mut self, id: u64) -> io::Result<()> {
+        self.append(&Record::Rollback { id })
+    }
+
+    /// Move a file, recording the operation in the journal
+    ///
+    /// Never overwrites the destination. On failure the source is left in place and any partial
+    /// copy is removed. Returns the id of the move.
+    pub fn move_file(
+        &mut self,
+        source: &Path,
+        destination: &Path,
+        reverts: Option<u64>,
+    ) -> io::Result<u64> {
+        println!("moving file: {source:?} -> {destination:?}");
+
+        if destination.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{destination:?} already exists")
+            ));
+        }
+
+        let id = self.begin(source, destination, reverts)?;
+
+        match file_store::move_file(source, destination) {
+            Ok(method) => {
+                println!("moved file ({method:?})");
+                self.commit(id)?;
+                Ok(id)
+            },
+            Err(e) => {
+                let _ = self.rollback(id);
+                Err(e)
+            },
+        }
+    }
+
+    /// Committed moves in the journal of `dir` that can still be undone, oldest first
+    ///
+    /// Moves that undo another one, and moves that were undone, are left out.
+    pub fn undoable(dir: &Path) -> io::Result<Vec<Move>> {
+        Ok(Self::undoable_in(Self::read_records(&dir.join(JOURNAL_FILE_NAME))?))
+    }
+
+    /// Finish or roll back every move in the journal of `dir` that never completed
+    ///
+    /// Copies only appear at the destination once verified, so a move whose destination exists is
+    /// considered complete and a leftover source is removed, after checking both have the same
+    /// contents. Unverified partial copies are discarded and the move is rolled back.
+    ///
+    /// The journal is then compacted to the last [KEEP_UNDOABLE] moves that can still be undone.
+    pub fn recover(dir: &Path) -> io::Result<Vec<Recovery>> {
+        let recovered = Self::finish_pending(dir)?;
+        Self::compact(dir)?;
+
+        Ok(recovered)
+    }
+
+    // -- private items
+    fn undoable_in(records: Vec<Record>) -> Vec<Move> {
+        let mut begun = BTreeMap::new();
+        let mut moves = BTreeMap::new();
+        let mut reverted = BTreeSet::new();
+
+        for record in records {
+            match record {
+                Record::Begin { id, source, destination, reverts } => {
+                    begun.insert(id, (Move { id, source, destination }, reverts));
+                },
+                Record::Commit { id } => {
+                    let Some((m, reverts)) = begun.remove(&id) else { continue };
+                    match reverts {
+                        Some(reverts) => {
+                            reverted.insert(reverts);
+                        },
+                        None => {
+                            moves.insert(id, m);
+                        },
+                    }
+                },
+                Record::Rollback { id } => {
+                    begun.remove(&id);
+                },
+            }
+        }
+
+        moves.into_values().filter(|m| !reverted.contains(&m.id)).collect()
+    }
+
+    /// See [Journal::recover]
+    fn finish_pending(dir: &Path) -> io::Result<Vec<Recovery>> {
+        let path = dir.join(JOURNAL_FILE_NAME);
+
+        // pending moves by id, in order
+        let mut pending = BTreeMap::new();
+        for record in Self::read_records(&path)? {
+            match record {
+                Record::Begin { id, source, destination, .. } => {
+                    pending.insert(id, (source, destination));
+                },
+                Record::Commit { id } | Record::Rollback { id } => {
+                    pending.remove(&id);
+                },
+            }
+        }
+
+        if pending.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut journal = Self::open(dir)?;
+        let mut recovered = Vec::with_capacity(pending.len());
+
+        for (id, (source, destination)) in pending {
+            // a copy interrupted before it was verified never reached the destination
+            let partial = file_store::partial_path(&destination);
+            if partial.exists() {
+                fs::remove_file(&partial)?;
+            }
+
+            let recovery = match (source.exists(), destination.exists()) {
+                // never delete the only copy of a file
+                (true, true) if !file_store::same_contents(&source, &destination)? => {
+                    journal.rollback(id)?;
+                    Recovery::Differs { source, destination }
+                },
+                (true, true) => {
+                    fs::remove_file(&source)?;
+                    journal.commit(id)?;
+                    Recovery::Completed { source, destination }
+                },
+                (false, true) => {
+                    journal.commit(id)?;
+                    Recovery::Completed { source, destination }
+                },
+                (true, false) => {
+                    journal.rollback(id)?;
+                    Recovery::RolledBack { source, destination }
+                },
+                (false, false) => {
+                    journal.rollback(id)?;
+                    Recovery::Missing { source, destination }
+                },
+            };
+
+            println!("journal recovery: {recovery}");
+            recovered.push(recovery);
+        }
+
+        Ok(recovered)
+    }
+
+    /// Rewrite the journal of `dir` with only the last [KEEP_UNDOABLE] moves that can be undone
+    ///
+    /// Only done once no move is pending. The newest move is always kept so ids keep counting up,
+    /// the originals kept by [orientation::bake] for the moves dropped are removed.
+    fn compact(dir: &Path) -> io::Result<()> {
+        let path = dir.join(JOURNAL_FILE_NAME);
+        let records = Self::read_records(&path)?;
+        let Some(last) = records.iter().map(Record::id).max() else { return Ok(()) };
+
+        let kept: BTreeSet<u64> = Self::undoable_in(records.clone()).iter()
+            .rev()
+            .take(KEEP_UNDOABLE)
+            .map(|m| m.id)
+            .chain([last])
+            .collect();
+
+        let compacted: Vec<&Record> = records.iter().filter(|r| kept.contains(&r.id())).collect();
+        if compacted.len() == records.len() {
+            return Ok(());
+        }
+
+        // written next to the journal and renamed over it, a crash leaves one or the other
+        let tmp = dir.join(format!("{JOURNAL_FILE_NAME}.tmp"));
+        let mut file = File::create(&tmp)?;
+        for record in compacted {
+            let mut line = serde_json::to_string(record)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        if let Ok(originals) = fs::read_dir(dir.join(orientation::ORIGINALS_DIR)) {
+            for entry in originals.flatten() {
+                let id: Option<u64> = entry.file_name().to_str().and_then(|n| n.parse().ok());
+                if id.is_some_and(|id| !kept.contains(&id)) {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    /// Read all records, a truncated trailing line (crash while appending) is skipped
+    fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut records = vec![];
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(r) => records.push(r),
+                Err(e) => eprintln!("skipping invalid journal entry {line:?}: {e}"),
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+impl Record {
+    pub fn id(&self) -> u64 {
+        match self {
+            Self::Begin { id, .. } | Self::Commit { id } | Self::Rollback { id } => *id,
+        }
+    }
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Completed { source, destination } =>
+                write!(f, "completed move {source:?} -> {destination:?}"),
+            Self::RolledBack { source, destination } =>
+                write!(f, "rolled back move {source:?} -> {destination:?}"),
+            Self::Missing { source, destination } =>
+                write!(f, "lost track of move {source:?} -> {destination:?}, neither file exists"),
+            Self::Differs { source, destination } =>
+                write!(f, "rolled back move {source:?} -> {destination:?}, both files exist and differ"),
+        }
+    }
+}
+
+/// Paths as strings, or as the raw platform encoding when they aren't valid unicode
+mod os_path {
+    use std::path::{Path, PathBuf};
+
+    #[cfg(unix)]
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    #[cfg(windows)]
+    use std::{ffi::OsString, os::windows::ffi::{OsStrExt, OsStringExt}};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Text(String),
+        #[cfg(unix)]
+        Bytes(Vec<u8>),
+        #[cfg(windows)]
+        Wide(Vec<u16>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        let raw = match path.to_str() {
+            Some(s) => Raw::Text(s.to_string()),
+            #[cfg(unix)]
+            None => Raw::Bytes(path.as_os_str().as_bytes().to_vec()),
+            #[cfg(windows)]
+            None => Raw::Wide(path.as_os_str().encode_wide().collect()),
+        };
+
+        raw.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Text(s) => PathBuf::from(s),
+            #[cfg(unix)]
+            Raw::Bytes(b) => PathBuf::from(OsStr::from_bytes(&b)),
+            #[cfg(windows)]
+            Raw::Wide(w) => PathBuf::from(OsString::from_wide(&w)),
+        })
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,48 @@
+//! # File sorter core
+//!
+//! Everything that doesn't need a window: the sorting queue and file moves, configuration, the
+//! file naming scheme, suggestions and the library index. The GUI binary is a frontend over this.
+
+/*--- Mod ----------------------------------------------------------------------------------------*/
+
+pub mod data_store;
+
+pub mod date;
+
+pub mod error;
+pub use error::Error;
+
+pub mod file_store;
+
+pub mod format;
+pub use format::ImageFormat;
+
+pub mod config;
+
+pub mod history;
+
+pub mod journal;
+
+pub mod metadata;
+
+pub mod orientation;
+
+pub mod index;
+
+pub mod query;
+
+pub mod queue;
+pub use queue::{MoveOutcome, SortQueue};
+
+pub mod cli;
+
+pub mod shortcuts;
+
+pub mod sorted_name;
+pub use sorted_name::{SortedName, TAG_SEPARATOR};
+
+pub mod text_suggest;
+
+pub mod viewport;
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
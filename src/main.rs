@@ -1,19 +1,22 @@
 //! # File sorter
+//!
+//! GUI frontend, the sorting logic lives in the [image_sorter] library
 
 #![allow(unused_imports)]
 
 /*--- Imports ------------------------------------------------------------------------------------*/
 
 use std::{
-    io::{self, prelude::*, Error},
+    io::{self, prelude::*},
     fs::{self, ReadDir},
     collections::{HashSet, HashMap},
     path::{PathBuf, Path},
-    sync::Arc
+    sync::Arc,
+    fmt,
 };
 
-use data_store::DataStore;
 pub use nannou::prelude::*;
+use nannou::image::GenericImageView;
 
 pub use nannou_egui::{
     self,
@@ -27,42 +30,91 @@ use clap::Parser;
 
 /*--- Mod ----------------------------------------------------------------------------------------*/
 
-mod data_store;
-
-mod config;
-use config::Config;
+mod decoder;
 
 mod image_manager;
 use image_manager::ImageManager;
 
-mod text_suggest;
+use image_sorter::{
+    cli::{self, CliCommand},
+    config::{CollisionPolicy, Config, ConfigError, NameKind, DEFAULT_CONFIG},
+    data_store::DataStore,
+    query::Query,
+    shortcuts::{self, Bindings, Command, KeyCombo},
+    metadata::{self, Metadata},
+    orientation::Orientation,
+    text_suggest,
+    viewport::{Viewport, Zoom},
+    Error,
+    MoveOutcome,
+};
 
 /*--- Global Constants ---------------------------------------------------------------------------*/
 
-const CONFIG_FILE_NAME: &'static str = ".image-sorter.yaml";
-pub const DEFAULT_CONFIG_S: &'static str = include_str!("../default/config.yaml");
-pub const TAG_SEPARATOR: &'static str = "--";
-
 lazy_static::lazy_static!{
-    static ref DEFAULT_CONFIG: Config = serde_yaml::from_str(DEFAULT_CONFIG_S)
-        .expect("failed to parse default configuration");
-
     pub static ref PLACEHOLDER_BUF: &'static [u8] =
         std::include_bytes!("../assets/placeholder.bmp");
 }
 
+/// Notifications kept on the panel, older ones are dropped
+const MAX_NOTIFICATIONS: usize = 5;
+
+/// Window space left at the bottom for the panel
+const PANEL_HEIGHT: f32 = 300.0;
+
+/// Space around the image
+const IMAGE_PAD: f32 = 45.0;
+
+/// Zoom change of the zoom in/out commands
+const ZOOM_STEP: f32 = 1.25;
+
+/// Fraction of the image area moved by the pan commands
+const PAN_STEP: f32 = 0.25;
+
+/*--- Types ---------------------------------------------------------------------------------------*/
+
+/// Change to the categories or tags requested from the panel
+#[derive(Debug, Clone)]
+pub enum TaxonomyEdit {
+    Add(NameKind, String),
+    Rename(NameKind, String, String),
+    Remove(NameKind, String),
+}
+
+/// Message shown in the notification area of the panel
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub level: Level,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
 /*--- Args ---------------------------------------------------------------------------------------*/
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Folder to sort, defaults to the current directory
     #[arg()]
-    folder: PathBuf,
+    folder: Option<PathBuf>,
+
+    /// Run a command without opening the window
+    #[command(subcommand)]
+    command: Option<CliCommand>,
 }
 
-impl From<PathBuf> for Args {
-    fn from(value: PathBuf) -> Self {
-        Self { folder: value.into() }
+impl Args {
+    fn folder(&self) -> PathBuf {
+        self.folder.clone().unwrap_or_else(|| {
+            // not on stdout, the output of the subcommands is read by scripts
+            eprintln!("using current dir as fallback");
+            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+        })
     }
 }
 
@@ -76,13 +128,29 @@ structstruck::strike!{
 
         config: DataStore<Config>,
 
-        image_manager: ImageManager,
+        /// Set while the folder config fails to load, sorting is blocked until it is fixed
+        config_error: Option<ConfigError>,
+
+        bindings: Bindings,
+
+        suggester: text_suggest::Suggester,
+
+        folder: PathBuf,
+
+        /// Error while the folder can't be opened, sorting is blocked until it is retried
+        image_manager: Result<ImageManager, Error>,
 
+        /// Where the image is drawn, follows the window size
+        image_area: Rect,
+
+        /// Fast sort mode, see [shortcuts]
         state:
-        #[derive(Debug, Clone, Copy, Default)]
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
         pub enum State {
+            /// Single keys trigger commands
             #[default]
             Idle,
+            /// Typing in the filename box
             Input,
         },
 
@@ -91,122 +159,59 @@ structstruck::strike!{
         pub struct {
             destination_filename: String,
             new_category: String,
+            /// Destination folder and existing file of a move waiting for the user to decide
+            pending_conflict: Option<(String, PathBuf)>,
+            /// Focus (true) or release (false) the filename box on the next frame
+            focus_request: Option<bool>,
             new_tag: String,
+            /// List and entry picked in the category/tag editor
+            edit_kind: NameKind,
+            edit_selected: String,
+            edit_new_name: String,
+            /// Library search window
+            search_open: bool,
+            search_query: String,
+            search_error: Option<String>,
+            search_results: Vec<PathBuf>,
+            /// Messages shown on the panel, oldest first
+            notifications: Vec<Notification>,
+            /// The image is being dragged with the mouse
+            image_drag: bool,
+            /// Image info side panel, its width and the details of the file it shows
+            info_open: bool,
+            info_width: f32,
+            info: Option<(PathBuf, io::Result<Metadata>)>,
         },
     }
 }
 
 impl Model {
-    pub fn new(_app: &App, egui: Egui) -> Self {
-        use clap::error::{self, ErrorKind, Error};
-
-        let args =
-        match Args::try_parse() {
-            Ok(a) => a,
+    pub fn new(app: &App, egui: Egui) -> Self {
+        // already validated by main
+        let folder = Args::parse().folder();
+
+        // load the folder configuration, a broken config is shown on the UI instead of silently
+        // replaced by the default one
+        let (config, config_error) = match Config::load(&folder) {
+            Ok(c) => {
+                println!("configuration: {:?}", *c);
+                (c, None)
+            },
             Err(e) => {
-                Args::from(
-                    match e.kind() {
-                        ErrorKind::MissingRequiredArgument => {
-                            println!("using current dir as fallback");
-                            std::env::current_dir()
-                                .expect("cannot read current dir but no args provided")
-                        },
-
-                        ErrorKind::DisplayHelp => {
-                            println!("{e}");
-                            std::process::exit(0);
-                        },
-
-                        _ => {
-                            panic!("unsupported");
-                        }
-                    })
-            }
+                eprintln!("config load error: {e}");
+                (DataStore::new_from_data(&e.path, DEFAULT_CONFIG.clone()), Some(e))
+            },
         };
 
-        // load configuration or default value
-        let config = {
-            let cfg_path = args.folder.join(CONFIG_FILE_NAME);
-
-            let cfg_str = fs::read_to_string(&cfg_path)
-                .map_err(|e| {
-                    // TODO: copy default config to the cwd
-                    println!("no local config present at {cfg_path:?}: {e}; ");
-                    println!("creating config file '{cfg_path:?}'");
-
-                    let _ = std::fs::OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(&cfg_path)
-                        .map(|mut f|
-                             f.write(DEFAULT_CONFIG_S.as_bytes())
-                             .map_err(|e| eprintln!("failed to write {cfg_path:?}: {e}"))
-                        )
-                        .map_err(|e| eprintln!("failed to create {cfg_path:?} : {e}"));
-
-                    e
-                })
-                .unwrap_or(DEFAULT_CONFIG_S.to_string());
-
-            let mut c: Config = serde_json::from_str(&cfg_str)
-                .map_err(|e| eprintln!("config load error: {e}"))
-                .unwrap_or(DEFAULT_CONFIG.clone());
-
-            println!("configuration: {c:?}");
-
-            // c.buttons = {
-            //     let mut tmp = c.buttons.drain().collect::<Vec<_>>();
-            //     tmp.sort_by(|prev, next| prev.0.cmp(&next.0));
-            //     tmp.into_iter().collect()
-            // };
-
-            // // make sure the trash button exists
-            // _ = c.buttons.insert(
-            //     "k".to_string(),
-            //     ButtonConfig {
-            //         button_label: "\u{1F5D1}".to_string(), // wastebasket symbol
-            //         label: "Trash".to_string(),
-            //         path: "trash".to_string(),
-            //     });
-
-            // c.buttons.iter_mut().for_each(|(b_id, b_cfg)| {
-            //     let old = b_cfg.label.clone();
-
-            //     let (up, low) = {
-            //         let c = b_id.chars().next().unwrap();
-            //         (c.to_ascii_lowercase(), c.to_ascii_uppercase())
-            //     };
-
-
-            //     let new =  {
-            //         let new = old.replacen(low, format!("[{}]", low).as_str(), 1);
-            //         if new == old {
-            //             old.replacen(up, format!("[{}]", up).as_str(), 1)
-            //         } else {
-            //             new
-            //         }
-            //     };
-
-            //     b_cfg.label.replace(
-            //         if new == old {
-            //             format!("{}: [{}]", old, b_id)
-            //         } else {
-            //             new
-            //         }.as_str()
-            //     );
-            // });
-
-            // TODO: implement keyboard shortcuts first or some kind
-            // of 'fast sort mode' with single key commands (vim-like)
-            // for (id, button) in &mut c.buttons {
-            //     button.label.replace(&button.label.replacen(id, &format!("[{}]", id), 1));
-            // }
-
-            DataStore::new_from_data(&args.folder, c)
-        };
+        let image_manager = ImageManager::new(&folder, &config);
 
-        Model {
-            image_manager: ImageManager::new(&args.folder, &config),
+        let mut model = Model {
+            bindings: Bindings::new(&config).unwrap_or_default(),
+            suggester: Default::default(),
+            image_manager,
+            image_area: canvas(app.window_rect(), 0.0).pad(IMAGE_PAD / 2.0),
+            config_error,
+            folder,
 
             // init to default
             ui_fields: Default::default(),
@@ -214,29 +219,266 @@ impl Model {
 
             config,
             egui,
+        };
+
+        model.setup_folder();
+        model
+    }
+
+    /// Try opening the folder again, see [Model::image_manager]
+    pub fn open_folder(&mut self) {
+        self.image_manager = ImageManager::new(&self.folder, &self.config);
+        self.setup_folder();
+    }
+
+    /// Create the output folders and index the library, once both the folder and its config load
+    fn setup_folder(&mut self) {
+        if self.image_manager.is_err() || self.config_error.is_some() {
+            return;
+        }
+
+        let shadowed: Vec<String> = self.bindings.warnings().iter()
+            .map(|c| match self.bindings.combo(&c.second) {
+                Some(combo) => format!("{c}, {combo} still triggers the button"),
+                None => format!("{c}, the button has no shortcut in idle mode"),
+            })
+            .collect();
+        for text in shadowed {
+            self.notify(Level::Info, text);
+        }
+
+        let Ok(manager) = &mut self.image_manager else { return };
+        manager.set_scale_filter(self.config.scale_filter);
+        manager.set_bake_orientation(self.config.bake_orientation);
+
+        let empty = manager.is_empty().then(|| manager.dir().to_path_buf());
+        let skipped = manager.skipped().len();
+        let res = manager.create_output_dirs(&self.config);
+        manager.reindex(&self.config);
+
+        if let Some(dir) = empty {
+            self.notify(Level::Info, format!("no supported files in {}", dir.display()));
+        }
+        if skipped > 0 {
+            self.notify(Level::Info, format!("{skipped} files skipped, see \"Skipped files\""));
+        }
+        if let Err(e) = res {
+            self.notify(Level::Error, e);
+        }
+    }
+
+    /// Show a message in the notification area
+    pub fn notify(&mut self, level: Level, text: impl fmt::Display) {
+        let text = text.to_string();
+        match level {
+            Level::Info => println!("{text}"),
+            Level::Error => eprintln!("{text}"),
+        }
+
+        let notifications = &mut self.ui_fields.notifications;
+        notifications.push(Notification { level, text });
+        if notifications.len() > MAX_NOTIFICATIONS {
+            notifications.remove(0);
+        }
+    }
+
+    /// Try loading the folder config again, clearing the error on success
+    pub fn reload_config(&mut self) {
+        match Config::load(&self.folder) {
+            Ok(c) => {
+                println!("configuration reloaded: {:?}", *c);
+                self.bindings = Bindings::new(&c).unwrap_or_default();
+                self.config = c;
+                self.config_error = None;
+                // the queue was built with the default config, e.g. its formats
+                self.open_folder();
+            },
+            Err(e) => {
+                eprintln!("config load error: {e}");
+                self.config_error = Some(e);
+            },
+        }
+    }
+
+    /// Run the search query against the library index
+    pub fn search(&mut self) {
+        let Ok(manager) = &self.image_manager else { return };
+        let fields = &mut self.ui_fields;
+
+        match fields.search_query.parse::<Query>() {
+            Ok(query) => {
+                let index = manager.index();
+                fields.search_results = query.filter(index.entries())
+                    .into_iter()
+                    .map(|e| index.root().join(&e.path))
+                    .collect();
+                fields.search_error = None;
+            },
+            Err(e) => {
+                fields.search_results.clear();
+                fields.search_error = Some(e);
+            },
+        }
+    }
+
+    /// Move the current image to `category` using the typed filename
+    ///
+    /// `collision` overrides the configured collision policy
+    pub fn move_current(&mut self, category: &str, collision: Option<CollisionPolicy>) {
+        let Ok(manager) = &mut self.image_manager else { return };
+
+        // don't sort the queue while looking at something else
+        if manager.preview().is_some() {
+            println!("showing a search result, back to the queue");
+            manager.reload_image();
+            return;
         }
 
+        let collision = collision.unwrap_or(self.config.collision);
+        let new_name = &mut self.ui_fields.destination_filename;
+
+        match manager.move_current(category, new_name, collision) {
+            Ok(MoveOutcome::Moved(path)) => {
+                println!("sorted into {path:?}");
+                new_name.clear();
+            },
+            Ok(MoveOutcome::Skipped { existing }) => {
+                let text = format!("identical file already exists at {}, skipped", existing.display());
+                self.notify(Level::Info, text);
+            },
+            Ok(MoveOutcome::Conflict { existing }) => {
+                self.ui_fields.pending_conflict = Some((category.to_string(), existing));
+            },
+            Err(e) => self.notify(Level::Error, e),
+        }
     }
 
-    pub fn add_category(&mut self, new_category: &str) {
-        self.config.categories.insert(new_category.to_string());
+    pub fn run_command(&mut self, command: &Command) {
+        let Ok(manager) = &mut self.image_manager else { return };
+
+        match command {
+            Command::Button(id) => {
+                if let Some(path) = self.config.buttons.get(id).map(|b| b.path.clone()) {
+                    self.move_current(&path, None);
+                }
+            },
+            Command::Next => manager.next_image(),
+            Command::Prev => manager.prev_image(),
+            Command::Undo => match manager.undo() {
+                Ok(Some(typed_name)) => self.ui_fields.destination_filename = typed_name,
+                Ok(None) => {},
+                Err(e) => self.notify(Level::Error, format!("undo failed: {e}")),
+            },
+            Command::Redo => {
+                if let Err(e) = manager.redo() {
+                    self.notify(Level::Error, format!("redo failed: {e}"));
+                }
+            },
+            Command::Open => {
+                let path = match manager.preview() {
+                    Some(p) => p.to_path_buf(),
+                    None => match manager.get_current_path() {
+                        Some(p) => p,
+                        None => return,
+                    },
+                };
+
+                // waited on in the background so the viewer doesn't linger as a zombie
+                match std::process::Command::new("xdg-open").arg(&path).spawn() {
+                    Ok(mut child) => { std::thread::spawn(move || child.wait()); },
+                    Err(e) => self.notify(Level::Error, Error::Open(path, e)),
+                }
+            },
+            Command::Confirm => {
+                let default_folder = self.config.default_folder.clone();
+                self.move_current(&default_folder, None);
+            },
+            Command::EnterInput => {
+                self.state = State::Input;
+                self.ui_fields.focus_request = Some(true);
+            },
+            Command::EnterIdle => {
+                self.state = State::Idle;
+                self.ui_fields.focus_request = Some(false);
+            },
+            Command::ZoomFit => self.change_view(|v, i, a| v.set_zoom(Zoom::Fit, i, a)),
+            Command::ZoomFillWidth => self.change_view(|v, i, a| v.set_zoom(Zoom::FillWidth, i, a)),
+            Command::ZoomActual => self.change_view(|v, i, a| v.set_zoom(Zoom::Actual, i, a)),
+            Command::ZoomIn => self.change_view(|v, i, a| v.zoom_by(ZOOM_STEP, [0.0; 2], i, a)),
+            Command::ZoomOut => self.change_view(|v, i, a| v.zoom_by(1.0 / ZOOM_STEP, [0.0; 2], i, a)),
+            Command::PanUp => self.change_view(|v, i, a| v.pan([0.0, -a[1] * PAN_STEP], i, a)),
+            Command::PanDown => self.change_view(|v, i, a| v.pan([0.0, a[1] * PAN_STEP], i, a)),
+            Command::PanLeft => self.change_view(|v, i, a| v.pan([-a[0] * PAN_STEP, 0.0], i, a)),
+            Command::PanRight => self.change_view(|v, i, a| v.pan([a[0] * PAN_STEP, 0.0], i, a)),
+        }
+    }
 
-        println!("added new category: {}", new_category);
+    /// Change the zoom or pan of the shown image, `change` gets the image and image area sizes
+    pub fn change_view(&mut self, change: impl FnOnce(&mut Viewport, [f32; 2], [f32; 2])) {
+        let Ok(manager) = &mut self.image_manager else { return };
+        let image = manager.image_size();
+        let area = self.image_area.wh().to_array();
 
-        todo!()
-        //std::fs::write(&self.config_path, &cfg_str).expect("cannot write preferences");
+        if let Some(viewport) = manager.viewport_mut() {
+            change(viewport, image, area);
+        }
+    }
+
+    /// Apply a change to the categories or tags and save the config
+    pub fn edit_taxonomy(&mut self, edit: TaxonomyEdit) {
+        // don't touch the config file while it's broken, the edit would overwrite it
+        if self.config_error.is_some() {
+            self.notify(Level::Error, "not editing categories or tags, the configuration failed to load");
+            return;
+        }
+
+        let changed = match &edit {
+            TaxonomyEdit::Add(kind, name) => self.config.add_name(*kind, name),
+            TaxonomyEdit::Rename(kind, from, to) => self.config.rename_name(*kind, from, to),
+            TaxonomyEdit::Remove(kind, name) => Ok(self.config.remove_name(*kind, name)),
+        };
+
+        match changed {
+            Ok(true) => {
+                println!("{edit:?}");
+
+                // an invalid name stays in its box to be fixed
+                if let TaxonomyEdit::Add(kind, _) = edit {
+                    match kind {
+                        NameKind::Category => self.ui_fields.new_category.clear(),
+                        NameKind::Tag => self.ui_fields.new_tag.clear(),
+                    }
+                }
+
+                // the rest of the file is left as it was written
+                if let Err(e) = self.config.save_fields(&["categories", "tags"]) {
+                    self.notify(Level::Error, format!("failed to save configuration: {e}"));
+                }
+            },
+            Ok(false) => println!("nothing to change for {edit:?}"),
+            Err(e) => self.notify(Level::Error, format!("invalid name: {e}")),
+        }
     }
 }
 
 /*--- Main application ---------------------------------------------------------------------------*/
 
-fn main() {
+fn main() -> std::process::ExitCode {
+    let mut args = Args::parse();
+
+    match args.command.take() {
+        None | Some(CliCommand::Sort) => {},
+        Some(command) => return cli::run(&args.folder(), command),
+    }
+
     nannou::app(model).update(update).run();
+    std::process::ExitCode::SUCCESS
 }
 
 /// Init function
 fn model(app: &App) -> Model {
-    app.set_exit_on_escape(true);
+    // escape leaves input mode
+    app.set_exit_on_escape(false);
 
     // Create window
     let window_id = app
@@ -254,66 +496,174 @@ fn model(app: &App) -> Model {
 
 /// Window and GUI update fn
 fn update(app: &App, model: &mut Model, update: Update) {
+    model.image_area = canvas(app.window_rect(), model.ui_fields.info_width).pad(IMAGE_PAD / 2.0);
     let egui = &mut model.egui;
-    let manager = &mut model.image_manager;
+
+    egui.set_elapsed_time(update.since_start);
+    let egui_context = egui.begin_frame();
+
+    // refuse to sort until the folder config loads
+    if let Some(error) = &model.config_error {
+        let mut reload = false;
+
+        egui::TopBottomPanel::bottom("Config Error").show(&egui_context, |ui| {
+            ui.heading("Failed to load the folder configuration");
+            ui.separator();
+            ui.label(error.to_string());
+            ui.separator();
+            ui.label("Fix the file and reload it, sorting is disabled until then.");
+            reload = ui.button("Reload configuration").clicked();
+        });
+
+        drop(egui_context);
+        if reload {
+            model.reload_config();
+        }
+
+        if let Ok(manager) = &mut model.image_manager {
+            manager.update_texture(app, model.image_area.wh().to_array());
+        }
+        return;
+    }
+
+    // nothing to sort until the folder opens
+    let manager = match &mut model.image_manager {
+        Ok(manager) => manager,
+        Err(error) => {
+            let mut retry = false;
+
+            egui::TopBottomPanel::bottom("Folder Error").show(&egui_context, |ui| {
+                ui.heading("Failed to open the folder");
+                ui.separator();
+                ui.label(error.to_string());
+                ui.separator();
+                ui.label("Fix the folder and try again, sorting is disabled until then.");
+                retry = ui.button("Retry").clicked();
+            });
+
+            drop(egui_context);
+            if retry {
+                model.open_folder();
+            }
+            return;
+        },
+    };
 
     let config = &model.config;
     let filename_buff = &mut model.ui_fields.destination_filename;
 
     let mut pos = manager.image_index as f32;
-    let max_img = (manager.get_images_len() - 1) as f32;
+    let max_img = manager.get_images_len().saturating_sub(1) as f32;
+
+    // commands requested through the UI or shortcuts, run once the frame is done
+    let mut commands: Vec<Command> = vec![];
+    let mut edits: Vec<TaxonomyEdit> = vec![];
+    let bindings = &model.bindings;
+    let state = &mut model.state;
+    let focus_request = model.ui_fields.focus_request.take();
+    let suggester = &mut model.suggester;
+
+    // search window
+    let mut run_search = false;
+    let mut preview: Option<Option<PathBuf>> = None;
+    egui::Window::new("Search library")
+        .open(&mut model.ui_fields.search_open)
+        .show(&egui_context, |ui| {
+            ui.horizontal(|ui| {
+                let r = ui.text_edit_singleline(&mut model.ui_fields.search_query);
+                run_search = ui.button("Search").clicked()
+                    || (r.lost_focus() && ui.input().key_pressed(egui::Key::Enter));
+            });
+            ui.label("e.g. anime and evangelion not sticker, category:meme size>1M date>=2023-01");
+
+            if let Some(error) = &model.ui_fields.search_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
 
-    egui.set_elapsed_time(update.since_start);
-    let egui_context = egui.begin_frame();
+            ui.label(format!("{} results", model.ui_fields.search_results.len()));
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for path in &model.ui_fields.search_results {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    let shown = manager.preview() == Some(path.as_path());
+                    if ui.selectable_label(shown, name).clicked() {
+                        preview = Some(Some(path.clone()));
+                    }
+                }
+            });
+
+            if manager.preview().is_some() && ui.button("Back to queue").clicked() {
+                preview = Some(None);
+            }
+        });
+
+    // collision dialog
+    let mut conflict_choice: Option<Option<CollisionPolicy>> = None;
+    if let Some((_, existing)) = &model.ui_fields.pending_conflict {
+        egui::Window::new("File already exists").show(&egui_context, |ui| {
+            ui.label(format!("{} already exists", existing.display()));
+            ui.horizontal(|ui| {
+                if ui.button("Keep both").clicked() {
+                    conflict_choice = Some(Some(CollisionPolicy::Suffix));
+                }
+                if ui.button("Skip if identical").clicked() {
+                    conflict_choice = Some(Some(CollisionPolicy::SkipIdentical));
+                }
+                if ui.button("Cancel").clicked() {
+                    conflict_choice = Some(None);
+                }
+            });
+        });
+    }
 
     //ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
 
     // GUI layout
     egui::TopBottomPanel::bottom("File Control").show(&egui_context, |ui| {
 
-        // Command detection
-        // {
-            // let input_state = ui.input();
-
-        //     for ev in &input_state.raw.events {
-        //         match ev {
-        //             egui::Event::Key {
-        //                 key,
-        //                 pressed: true,
-        //                 modifiers: egui::Modifiers { command, ..},
-        //             } => {
-        //                 // fixed delete key
-        //                 match key {
-        //                     // egui::Key::Delete => {
-        //                     //     let btn_cfg = config.buttons.get("t").unwrap();
-        //                     //     manager.move_current(&btn_cfg.path, filename_buff);
-        //                     // },
-
-        //                     // egui::Key::ArrowDown | egui::Key::ArrowLeft => {
-        //                     //     manager.prev_image();
-        //                     // },
-
-        //                     // egui::Key::ArrowUp | egui::Key::ArrowRight => {
-        //                     //     manager.next_image();
-        //                     // },
-
-        //                     _ => {
-        //                         if *command {
-        //                             let id:&str = dbg!(stringify!(key));
-        //                             if let Some(btn_cfg) = config.buttons.get(id) {
-        //                                 manager.move_current(&btn_cfg.path, filename_buff);
-        //                             }
-        //                         }
-        //                     }
-        //                 }
-
-        //             },
-        //             _ => {},
-        //         }
-        //     }
-        // }
-
-        ui.label(format!("current image: {}", manager.get_current_filename()));
+        ui.label(match state {
+            State::Idle => "-- IDLE -- (fast sort)",
+            State::Input => "-- INPUT --",
+        });
+        let loading = if manager.is_loading() { " (loading...)" } else { "" };
+        match manager.preview() {
+            Some(path) => ui.label(format!("search result: {}{loading}", path.display())),
+            None => ui.label(format!(
+                "current image: {}{loading}",
+                manager.get_current_display_name().as_deref().unwrap_or("(none left)")
+            )),
+        };
+
+        // notifications, newest first
+        if !model.ui_fields.notifications.is_empty() {
+            ui.separator();
+
+            let mut dismissed = None;
+            for (i, notification) in model.ui_fields.notifications.iter().enumerate().rev() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("x").clicked() {
+                        dismissed = Some(i);
+                    }
+                    match notification.level {
+                        Level::Info => ui.label(&notification.text),
+                        Level::Error => ui.colored_label(egui::Color32::RED, &notification.text),
+                    };
+                });
+            }
+
+            if let Some(i) = dismissed {
+                model.ui_fields.notifications.remove(i);
+            }
+        }
+
+        // labels show the keys of the current mode
+        let idle = *state == State::Idle;
+        let label = |text: &str, command: &Command| {
+            if idle {
+                bindings.idle_label(text, command)
+            } else {
+                bindings.label(text, command)
+            }
+        };
 
         ui.separator();
         ui.label("Controls");
@@ -325,18 +675,46 @@ fn update(app: &App, model: &mut Model, update: Update) {
                 manager.seek_to_image(pos as usize);
             }
 
-            if col[1].button("Open file in default program").clicked() {
-                let _ = std::process::Command::new("xdg-open")
-                    .arg(manager.get_current_path())
-                    .spawn()
-                    .unwrap();
+            if col[1].button(label("Open file in default program", &Command::Open)).clicked() {
+                commands.push(Command::Open);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Search library").clicked() {
+                model.ui_fields.search_open = true;
+            }
+            ui.checkbox(&mut model.ui_fields.info_open, "Image info");
+        });
+
+        // zoom, the wheel scrolls the image and zooms with Ctrl, dragging pans
+        ui.horizontal(|ui| {
+            let viewport = manager.viewport();
+            let factor = viewport.zoom.factor(manager.image_size(), model.image_area.wh().to_array());
+            ui.label(format!("Zoom {:.0} %", factor * 100.0));
+
+            for (text, zoom, command) in [
+                ("Fit", Zoom::Fit, Command::ZoomFit),
+                ("Fill width", Zoom::FillWidth, Command::ZoomFillWidth),
+                ("1:1", Zoom::Actual, Command::ZoomActual),
+            ] {
+                if ui.selectable_label(viewport.zoom == zoom, label(text, &command)).clicked() {
+                    commands.push(command);
+                }
+            }
+
+            if ui.button(label("-", &Command::ZoomOut)).clicked() {
+                commands.push(Command::ZoomOut);
+            }
+            if ui.button(label("+", &Command::ZoomIn)).clicked() {
+                commands.push(Command::ZoomIn);
             }
         });
 
         let create_movement_buttons = |col: &mut [egui::Ui]| {
             {
                 let c_ui = &mut col[0];
-                c_ui.label("Prev");
+                c_ui.label(label("Prev", &Command::Prev));
                 let btn = c_ui.add_enabled(manager.image_index != 0, egui::Button::new(" ⏴ "));
                 if btn.clicked() {
                     manager.prev_image();
@@ -351,9 +729,9 @@ fn update(app: &App, model: &mut Model, update: Update) {
             }
             {
                 let c_ui = &mut col[1];
-                c_ui.label("Next");
+                c_ui.label(label("Next", &Command::Next));
                 let btn = c_ui.add_enabled(
-                    manager.image_index != (manager.get_images_len() - 1),
+                    manager.image_index != manager.get_images_len().saturating_sub(1),
                     egui::Button::new(" ⏵ "),
                 );
 
@@ -374,29 +752,43 @@ fn update(app: &App, model: &mut Model, update: Update) {
             ui.columns(2, create_movement_buttons)
         });
 
-        let create_buttons = |col: &mut [egui::Ui]| {
-             {
-                 let mut pos = 0;
-                 // TODO: add keyboard shortcuts using C - 'button ids'
-                 // TODO: allow for reordering buttons with a configuration field
-                 // (currently based hashmap keys order afaik)
-                 for (_button_id, button_cfg) in &config.buttons {
-                     let c_ui = &mut col[pos];
-                     pos += 1;
-                     c_ui.label(button_cfg.label.as_str());
-
-                     let btn = c_ui.button(format!("  {}  ", button_cfg.button_label));
-                     if btn.clicked() {
-                         manager.move_current(&button_cfg.path, filename_buff);
-                     }
-                 }
+        // Undo/Redo
+        ui.columns(2, |col| {
+            let undo = egui::Button::new(label("Undo", &Command::Undo));
+            if col[0].add_enabled(manager.can_undo(), undo).clicked() {
+                commands.push(Command::Undo);
             }
-        };
 
-        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-            ui.columns(config.buttons.len(), create_buttons)
+            let redo = egui::Button::new(label("Redo", &Command::Redo));
+            if col[1].add_enabled(manager.can_redo(), redo).clicked() {
+                commands.push(Command::Redo);
+            }
         });
 
+        // Category buttons, one row per layout group wrapping at `button_columns`
+        for (group_label, buttons) in config.button_rows() {
+            if let Some(group_label) = group_label {
+                ui.label(group_label);
+            }
+
+            for row in buttons.chunks(config.button_columns.max(1)) {
+                ui.columns(row.len(), |col| {
+                    for (c_ui, (button_id, button_cfg)) in col.iter_mut().zip(row) {
+                        let command = Command::Button(button_id.to_string());
+
+                        c_ui.with_layout(egui::Layout::top_down(egui::Align::Center), |c_ui| {
+                            c_ui.label(label(&button_cfg.label, &command));
+
+                            let btn = c_ui.button(format!("  {}  ", button_cfg.button_label));
+                            if btn.clicked() {
+                                commands.push(command);
+                            }
+                        });
+                    }
+                });
+            }
+        }
+
         // Progress bar
         ui.separator();
         {
@@ -423,197 +815,352 @@ fn update(app: &App, model: &mut Model, update: Update) {
                 .lock_focus(true), //.cursor_at_end(true)
         );
 
-        let seg_buff = filename_buff.clone();
-        let mut segments = text_suggest::get_segments(&seg_buff);
-
-         let suggestions: Vec<String> = {
-             if let Some(segment) = segments.last() {
-                 todo!("get suggestions and check if first position to not suggest tags");
-                // text_suggest:: .get_suggestions(&segment)
-            } else {
-                vec![]
-            }
-         };
-
-
-        //     // let popup_id = ui.make_persistent_id("suggestions_box");
-
-        //     // if suggestions.first().is_some() {
-        //     //     ui.memory().toggle_popup(popup_id);
-        //     // }
+        match focus_request {
+            Some(true) => inputbox_r.request_focus(),
+            Some(false) => inputbox_r.surrender_focus(),
+            None => {},
+        }
 
-        //     // egui::popup_below_widget(ui, popup_id, &inputbox_r, |ui| {
-        //     //     for sug in &suggestions {
-        //     //         ui.label(sug);
-        //     //     }
-        //     // });
+        if inputbox_r.gained_focus() {
+            *state = State::Input;
+        }
 
+        let dataset = text_suggest::Dataset {
+            categories: &config.categories,
+            tags: &config.tags,
+            generation: config.generation(),
+        };
+        let suggestions = suggester.suggestions(dataset, filename_buff);
 
         let k = ui.input();
 
-        if inputbox_r.lost_focus() {
+        // keyboard shortcuts, while typing only the ones using Ctrl/Alt are active so they don't
+        // eat text input, except for confirming the filename and leaving input mode
+        {
+            let filename_active = inputbox_r.has_focus() || inputbox_r.lost_focus();
+            let typing = ui.ctx().wants_keyboard_input() || filename_active;
+
+            for ev in &k.events {
+                let egui::Event::Key { key, pressed: true, modifiers } = ev else { continue };
+                let combo = key_combo(*key, *modifiers);
+
+                let command = if *state == State::Idle && !typing {
+                    bindings.idle_command(&combo)
+                } else {
+                    bindings.command(&combo)
+                };
+                let Some(command) = command else { continue };
+
+                let allowed = !typing
+                    || combo.ctrl
+                    || combo.alt
+                    || (filename_active && matches!(command, Command::Confirm | Command::EnterIdle));
+
+                if allowed {
+                    commands.push(command.clone());
+                }
+            }
+        }
+
+        // Enter confirms and keeps typing, anything else taking the focus goes back to fast sort
+        if *state == State::Input && inputbox_r.lost_focus() {
             if k.key_pressed(egui::Key::Enter) {
-                manager.move_current(&config.default_folder, filename_buff);
-                filename_buff.clear();
                 inputbox_r.request_focus();
+            } else {
+                *state = State::Idle;
             }
         }
 
-        // tab pressed
+        // tab pressed, complete or cycle through the candidates
         if k.key_released(egui::Key::Tab) {
+            if let Some(completed) = suggester.complete(dataset, filename_buff) {
+                println!("completion: {completed:?}");
+                *filename_buff = completed;
 
-            if let (Some(replacement), Some(dest)) =
-                (suggestions.first(), segments.last_mut())
-            {
-                *dest = replacement;
-
-                // get the new segment and cycle try cycling between options
-                // if let Some(ref mut sel) = dbg!(text_suggest.current_selection) {
-                //     if let Some(sug_next) = &suggestions.get(*sel + 1) {
-                //         *sel += 1;
-                //         sug_next
-                //     } else {
-                //         dbg!(*sel);
-                //         *sel = 0;
-                //         replacement
-                //     }
-                // } else {
-                //     text_suggest.current_selection = Some(0);
-                //     replacement
-                // };
-
-                println!("completion: {replacement:?}");
-
-                // replace name with new string
-                *filename_buff =
-                    segments.iter().fold(String::new(), |mut acc, part| {
-                        acc.push_str(part);
-                        acc.push_str(text_suggest::SEPARATOR);
-                        acc
-                    });
+                // drop the widget state so the cursor is placed at the end again
+                ui.memory().id_data.remove(&inputbox_r.id);
+            }
+        }
+
+        let selected = suggester.selected(filename_buff);
+        ui.label(format!(
+            "Suggestions: {}",
+            suggestions.iter()
+                .take(5)
+                .map(|s| if Some(s.as_str()) == selected { format!("[{s}]") } else { s.clone() })
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+
+        // new category and tag boxes, prefilled from the filename being typed
+        if inputbox_r.changed() {
+            let segments = text_suggest::get_segments(filename_buff);
+            if let Some(segment) = segments.first() {
+                model.ui_fields.new_category.replace(segment);
+            }
+            if let (true, Some(segment)) = (segments.len() > 1, segments.last()) {
+                model.ui_fields.new_tag.replace(segment);
             }
         }
 
-        let mut suggestions_iter = suggestions.iter();
-        let first: String = suggestions_iter
-            .next()
-            .map(|i| i.clone())
-            .unwrap_or(" ".to_string());
-
-        ui.label(
-            format!("Suggestions: {}",
-            suggestions_iter.take(2)
-                    .fold(first, |mut res, item| {
-                        res.push_str(", ");
-                        res.push_str(item);
-                        res
-                    })
-            ));
-
-        // new category box
         ui.separator();
         ui.separator();
-        ui.label("Add new category");
-        ui.columns(2, |col| {
+        for (kind, text, buff) in [
+            (NameKind::Category, "Add new category", &mut model.ui_fields.new_category),
+            (NameKind::Tag, "Add new tag", &mut model.ui_fields.new_tag),
+        ] {
+            ui.label(text);
+            ui.columns(2, |col| {
+                col[0].text_edit_singleline(buff);
+
+                let known = config.names(kind).contains(buff.trim());
+                if col[1].add_enabled(!known, egui::Button::new(" \u{002b} ")).clicked() {
+                    edits.push(TaxonomyEdit::Add(kind, buff.trim().to_string()));
+                }
+            });
+        }
 
-            // set the input buffer as always the last segment if it exists
-            if let Some(segment) = segments.last() {
-                model.ui_fields.new_category.replace(segment);
-            }
+        // files left out of the queue
+        let skipped = manager.skipped();
+        if !skipped.is_empty() {
+            egui::CollapsingHeader::new(format!("Skipped files ({})", skipped.len())).show(ui, |ui| {
+                egui::ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                    for s in skipped {
+                        ui.label(format!("{}: {}", s.name.to_string_lossy(), s.reason));
+                    }
+                });
+            });
+        }
 
-            col[0].text_edit_singleline(&mut model.ui_fields.new_category);
-            if col[1].button(" \u{002b} ").clicked() {
-                todo!("add categories")
-                // model.add_category(&model.ui_fields.new_category);
-                // model.ui_fields.new_category.clear();
-                //text_suggest.add_category();
+        // rename/remove existing entries
+        egui::CollapsingHeader::new("Edit categories and tags").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut model.ui_fields.edit_kind, NameKind::Category, "Categories");
+                ui.radio_value(&mut model.ui_fields.edit_kind, NameKind::Tag, "Tags");
+            });
+
+            let names = config.names(model.ui_fields.edit_kind);
+            if !names.contains(&model.ui_fields.edit_selected) {
+                model.ui_fields.edit_selected.clear();
             }
+
+            egui::ComboBox::from_id_source("Edit taxonomy")
+                .selected_text(model.ui_fields.edit_selected.as_str())
+                .show_ui(ui, |ui| {
+                    for name in names {
+                        if ui.selectable_label(model.ui_fields.edit_selected == *name, name).clicked() {
+                            model.ui_fields.edit_selected = name.clone();
+                            model.ui_fields.edit_new_name = name.clone();
+                        }
+                    }
+                });
+
+            ui.add_enabled_ui(!model.ui_fields.edit_selected.is_empty(), |ui| {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut model.ui_fields.edit_new_name);
+
+                    if ui.button("Rename").clicked() {
+                        edits.push(TaxonomyEdit::Rename(
+                            model.ui_fields.edit_kind,
+                            model.ui_fields.edit_selected.clone(),
+                            model.ui_fields.edit_new_name.clone(),
+                        ));
+                    }
+
+                    if ui.button("Remove").clicked() {
+                        edits.push(TaxonomyEdit::Remove(model.ui_fields.edit_kind, model.ui_fields.edit_selected.clone()));
+                        model.ui_fields.edit_new_name.clear();
+                    }
+                });
+            });
         });
     });
 
-    manager.update_texture(app);
-}
+    // details of the shown file, read again when it changes, next to the image
+    let fields = &mut model.ui_fields;
+    if fields.info_open {
+        let shown = manager.shown();
+        if fields.info.as_ref().map(|(p, _)| p.as_path()) != shown {
+            fields.info = shown.map(|p| (p.to_path_buf(), Metadata::read(p)));
+        }
 
-/// Drawing loop
-fn view(app: &App, model: &Model, frame: Frame) {
-    const PAD: f32 = 45.0;
+        let r = egui::SidePanel::right("Image Info").show(&egui_context, |ui| {
+            ui.heading("Image info");
+            ui.separator();
+
+            let meta = match &fields.info {
+                Some((_, Ok(meta))) => meta,
+                Some((path, Err(e))) => {
+                    ui.colored_label(egui::Color32::RED, format!("failed to read {}: {e}", path.display()));
+                    return;
+                },
+                None => {
+                    ui.label("no image");
+                    return;
+                },
+            };
+
+            let mut rows = vec![];
+            match manager.image() {
+                Some(img) => {
+                    let (w, h) = img.dimensions();
+                    rows.push(("Dimensions", format!("{w} x {h}")));
+                    rows.push(("Color", format!("{:?}", img.color())));
+                },
+                None if manager.is_loading() => rows.push(("Dimensions", "loading...".to_string())),
+                None => rows.push(("Dimensions", "failed to decode".to_string())),
+            }
 
-    let draw = app.draw();
-    frame.clear(BLACK);
+            let format = meta.format.map_or_else(|| "unknown".to_string(), |f| f.to_string());
+            rows.push(("Format", format));
+            rows.push(("Size", metadata::format_size(meta.size)));
+            if let Some(modified) = meta.modified {
+                rows.push(("Modified", metadata::format_time(modified)));
+            }
+            if let Some(camera) = &meta.camera {
+                rows.push(("Camera", camera.clone()));
+            }
+            if let Some(taken) = &meta.taken {
+                rows.push(("Taken", taken.clone()));
+            }
+            if meta.orientation != Orientation::Normal {
+                rows.push(("Orientation", format!("{:?}", meta.orientation)));
+            }
+            if let Some(name) = &meta.name {
+                rows.push(("Category", name.category().to_string()));
+                rows.push(("Tags", name.tags().join(", ")));
+                rows.push(("Original name", name.original().to_string()));
+            }
 
-    let win = app.window_rect();
-    let canvas = Rect::from(win.clone()).top_left_of(win).pad_bottom(300.0);
+            egui::Grid::new("Image Info Grid").num_columns(2).striped(true).show(ui, |ui| {
+                for (key, value) in rows {
+                    ui.label(key);
+                    ui.label(value);
+                    ui.end_row();
+                }
+            });
+        });
+        fields.info_width = r.response.rect.width();
+    } else {
+        fields.info = None;
+        fields.info_width = 0.0;
+    }
 
-    let img_texture = model.image_manager.get_texture();
+    // mouse over the image, ignored while egui uses it
+    let input = egui_context.input();
+    let free_pointer = !egui_context.wants_pointer_input();
+    let scroll = input.scroll_delta;
+    let zoom_anchor = input.pointer.hover_pos().map(|pos| {
+        // egui points start at the top left corner, the image area is centered on nannou's origin
+        let (win, area) = (app.window_rect(), model.image_area);
+        [pos.x - win.w() / 2.0 - area.x(), pos.y - win.h() / 2.0 + area.y()]
+    });
+    let ctrl = input.modifiers.ctrl || input.modifiers.mac_cmd;
+    let shift = input.modifiers.shift;
 
-    let [img_w, img_h] = img_texture.size();
+    if input.pointer.any_pressed() {
+        model.ui_fields.image_drag = free_pointer && !egui_context.is_pointer_over_area();
+    } else if !input.pointer.primary_down() {
+        model.ui_fields.image_drag = false;
+    }
+    let drag = model.ui_fields.image_drag.then(|| input.pointer.delta());
+
+    drop(egui_context);
+
+    if free_pointer && scroll != egui::Vec2::ZERO {
+        match (ctrl, zoom_anchor) {
+            (true, Some(anchor)) => {
+                model.change_view(|v, i, a| v.zoom_by((scroll.y * 0.005).exp(), anchor, i, a));
+            },
+            _ => {
+                // the wheel scrolls vertically, horizontally with Shift
+                let delta = if shift && scroll.x == 0.0 { [-scroll.y, 0.0] } else { [-scroll.x, -scroll.y] };
+                model.change_view(|v, i, a| v.pan(delta, i, a));
+            },
+        }
+    }
 
-    #[allow(unused)]
-    let mut dbg_text = String::new();
+    if let Some(delta) = drag.filter(|d| *d != egui::Vec2::ZERO) {
+        model.change_view(|v, i, a| v.pan([-delta.x, -delta.y], i, a));
+    }
 
-    // scale image preserving proportions
-    let wh: Vec2 = {
-        let img_w_f = img_w as f32;
-        let img_h_f = img_h as f32;
-        let img_h_fit = img_h_f * (canvas.w() / img_w_f);
-        let img_w_fit = img_w_f * (canvas.h() / img_h_f);
+    if let Some(choice) = conflict_choice {
+        if let (Some(collision), Some((category, _))) = (choice, model.ui_fields.pending_conflict.take()) {
+            model.move_current(&category, Some(collision));
+        }
+        model.ui_fields.pending_conflict = None;
+    }
 
-        let fit_to_width = || {
-            // fit to width
-            Vec2::new(canvas.w() - PAD, img_h_fit - PAD)
-        };
+    if run_search {
+        model.search();
+    }
 
-        let fit_to_height = || {
-            // fit to height
-            Vec2::new(img_w_fit - PAD, canvas.h() - PAD)
-        };
+    match (preview, &mut model.image_manager) {
+        (Some(Some(path)), Ok(manager)) => manager.show_preview(&path),
+        (Some(None), Ok(manager)) => manager.reload_image(),
+        _ => {},
+    }
 
-        if img_w > img_h {
-            if img_h_fit < canvas.h() {
-                //dbg_text += "1".into();
+    for command in commands {
+        model.run_command(&command);
+    }
 
-                fit_to_width()
-            } else {
-                //dbg_text += "2".into();
+    for edit in edits {
+        model.edit_taxonomy(edit);
+    }
 
-                fit_to_height()
-            }
-        } else {
-            if img_h_fit > canvas.h() {
-                //dbg_text += "3".into();
+    if let Ok(manager) = &mut model.image_manager {
+        manager.update_texture(app, model.image_area.wh().to_array());
+    }
+}
 
-                fit_to_height()
-            } else {
-                //dbg_text += "4".into();
+/// Drawing loop
+fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    frame.clear(BLACK);
 
-                fit_to_width()
-            }
-        }
+    let win = app.window_rect();
+    let canvas = canvas(win, model.ui_fields.info_width);
+    let area = model.image_area;
+
+    // only the panel until the folder opens
+    let Ok(manager) = &model.image_manager else {
+        model.egui.draw_to_frame(&frame).unwrap();
+        return;
+    };
+    let Some(img_texture) = manager.get_texture() else {
+        model.egui.draw_to_frame(&frame).unwrap();
+        return;
     };
 
-    let xy = Point2::new(canvas.x(), canvas.y());
+    // zoomed images don't spill out of the canvas
+    let draw = draw.scissor(canvas);
 
-    //let view = model.manager.current_image.0.view().build();
+    let ([w, h], [x, y]) = manager.viewport().layout(manager.image_size(), area.wh().to_array());
+    let xy = Point2::new(area.x() + x, area.y() - y);
+    let wh = Vec2::new(w, h);
 
     // bg rect
     draw.rect()
-        //.xy(canvas.wh())
         .wh(canvas.wh())
         .xy(canvas.xy())
         .color(BLACK);
 
     draw.rect()
         .xy(xy)
-        .wh(wh+Vec2::new(PAD, PAD))
+        .wh(wh + Vec2::new(IMAGE_PAD, IMAGE_PAD))
         .color(DARKGREY);
 
     draw.texture(img_texture.as_ref())
         .xy(xy)
         .wh(wh);
 
-    // draw.text(&dbg_text)
-    //     .xy(canvas.mid_top())
-    //     .font_size(25);
+    if manager.is_loading() {
+        draw.text("Loading...")
+            .xy(area.xy())
+            .font_size(25)
+            .color(WHITE);
+    }
 
     // run queued drawing commands
     draw.to_frame(app, &frame).unwrap();
@@ -622,6 +1169,79 @@ fn view(app: &App, model: &Model, frame: Frame) {
     model.egui.draw_to_frame(&frame).unwrap();
 }
 
+/// Part of the window `win` left for the image, above the panel and left of a side panel `right`
+/// wide
+fn canvas(win: Rect, right: f32) -> Rect {
+    win.top_left_of(win).pad_bottom(PANEL_HEIGHT).pad_right(right)
+}
+
+/// Convert an egui key press into a [KeyCombo]
+fn key_combo(key: egui::Key, modifiers: egui::Modifiers) -> KeyCombo {
+    use egui::Key as E;
+    use shortcuts::Key as K;
+
+    let key = match key {
+        E::ArrowDown => K::Down,
+        E::ArrowLeft => K::Left,
+        E::ArrowRight => K::Right,
+        E::ArrowUp => K::Up,
+        E::Escape => K::Escape,
+        E::Tab => K::Tab,
+        E::Backspace => K::Backspace,
+        E::Enter => K::Enter,
+        E::Space => K::Space,
+        E::Insert => K::Insert,
+        E::Delete => K::Delete,
+        E::Home => K::Home,
+        E::End => K::End,
+        E::PageUp => K::PageUp,
+        E::PageDown => K::PageDown,
+        E::Num0 => K::Char('0'),
+        E::Num1 => K::Char('1'),
+        E::Num2 => K::Char('2'),
+        E::Num3 => K::Char('3'),
+        E::Num4 => K::Char('4'),
+        E::Num5 => K::Char('5'),
+        E::Num6 => K::Char('6'),
+        E::Num7 => K::Char('7'),
+        E::Num8 => K::Char('8'),
+        E::Num9 => K::Char('9'),
+        E::A => K::Char('a'),
+        E::B => K::Char('b'),
+        E::C => K::Char('c'),
+        E::D => K::Char('d'),
+        E::E => K::Char('e'),
+        E::F => K::Char('f'),
+        E::G => K::Char('g'),
+        E::H => K::Char('h'),
+        E::I => K::Char('i'),
+        E::J => K::Char('j'),
+        E::K => K::Char('k'),
+        E::L => K::Char('l'),
+        E::M => K::Char('m'),
+        E::N => K::Char('n'),
+        E::O => K::Char('o'),
+        E::P => K::Char('p'),
+        E::Q => K::Char('q'),
+        E::R => K::Char('r'),
+        E::S => K::Char('s'),
+        E::T => K::Char('t'),
+        E::U => K::Char('u'),
+        E::V => K::Char('v'),
+        E::W => K::Char('w'),
+        E::X => K::Char('x'),
+        E::Y => K::Char('y'),
+        E::Z => K::Char('z'),
+    };
+
+    KeyCombo {
+        ctrl: modifiers.ctrl || modifiers.mac_cmd,
+        alt: modifiers.alt,
+        shift: modifiers.shift,
+        key,
+    }
+}
+
 /// Let egui handle things like keyboard and mouse input.
 fn raw_window_event(_app: &App, model: &mut Model, event: &nannou::winit::event::WindowEvent) {
     model.egui.handle_raw_event(event);
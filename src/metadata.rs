@@ -0,0 +1,171 @@
+//! File details shown for an image
+//!
+//! What can be read without decoding the pixels: file size and date, format, the EXIF camera
+//! fields and the [SortedName] of files that were already sorted.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use crate::{
+    date::{civil_from_days, SECONDS_PER_DAY},
+    format::ImageFormat,
+    orientation::Orientation,
+    sorted_name::SortedName,
+};
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Details of an image file
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// In bytes
+    pub size: u64,
+    /// Modification time in seconds since the unix epoch
+    pub modified: Option<u64>,
+    pub format: Option<ImageFormat>,
+    /// EXIF make and model
+    pub camera: Option<String>,
+    /// EXIF date the photo was taken, `YYYY-MM-DD HH:MM:SS` in the camera's time zone
+    pub taken: Option<String>,
+    pub orientation: Orientation,
+    /// Category and tags of files named by the sorter
+    pub name: Option<SortedName>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Metadata {
+    /// Details of the file at `path`, missing or broken EXIF data is left out
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        let modified = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        let exif = File::open(path).ok()
+            .and_then(|f| exif::Reader::new().read_from_container(&mut BufReader::new(f)).ok());
+        let field = |tag| exif.as_ref().and_then(|e| e.get_field(tag, exif::In::PRIMARY));
+        let text = |tag| field(tag).and_then(ascii);
+
+        // the model often repeats the make
+        let camera = match (text(exif::Tag::Make), text(exif::Tag::Model)) {
+            (Some(make), Some(model)) if !model.starts_with(&make) => Some(format!("{make} {model}")),
+            (make, model) => model.or(make),
+        };
+
+        let taken = [exif::Tag::DateTimeOriginal, exif::Tag::DateTime].into_iter()
+            .find_map(|tag| {
+                let exif::Value::Ascii(v) = &field(tag)?.value else { return None };
+                exif::DateTime::from_ascii(v.first()?).ok()
+            })
+            .map(|date| date.to_string());
+
+        let orientation = field(exif::Tag::Orientation)
+            .and_then(|f| f.value.get_uint(0))
+            .and_then(Orientation::from_exif)
+            .unwrap_or_default();
+
+        Ok(Self {
+            size: meta.len(),
+            modified,
+            format: ImageFormat::detect(path)?,
+            camera,
+            taken,
+            orientation,
+            name: path.file_name().and_then(|f| f.to_str()).and_then(|f| f.parse().ok()),
+        })
+    }
+}
+
+/// Human readable size, in powers of 1024
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC` of `secs` since the unix epoch
+pub fn format_time(secs: u64) -> String {
+    let secs = secs as i64;
+    let (y, m, d) = civil_from_days(secs.div_euclid(SECONDS_PER_DAY));
+    let time = secs.rem_euclid(SECONDS_PER_DAY);
+
+    format!("{y:04}-{m:02}-{d:02} {:02}:{:02}:{:02} UTC", time / 3600, time / 60 % 60, time % 60)
+}
+
+// -- private items
+/// Text of an EXIF ASCII field, trimmed
+fn ascii(field: &exif::Field) -> Option<String> {
+    let exif::Value::Ascii(v) = &field.value else { return None };
+    let text = String::from_utf8_lossy(v.first()?);
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(1024 * 1024), "1.0 MiB");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+        assert_eq!(format_size(2 << 40), "2048.0 GiB", "no unit past GiB");
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(86_399), "1970-01-01 23:59:59 UTC");
+
+        let leap_day = crate::date::days_from_civil(2024, 2, 29) * SECONDS_PER_DAY;
+        assert_eq!(format_time(leap_day as u64 + 45_296), "2024-02-29 12:34:56 UTC");
+        assert_eq!(format_time(leap_day as u64 + 86_400), "2024-03-01 00:00:00 UTC");
+    }
+
+    #[test]
+    fn read_without_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("anime--sticker__a.png");
+        fs::write(&path, b"\x89PNG\r\n\x1a\nnot really").unwrap();
+
+        let meta = Metadata::read(&path).unwrap();
+        assert_eq!(meta.size, 18);
+        assert!(meta.modified.is_some());
+        assert_eq!(meta.format, Some(ImageFormat::Png));
+        assert_eq!(meta.camera, None);
+        assert_eq!(meta.taken, None);
+        assert_eq!(meta.orientation, Orientation::Normal);
+
+        let name = meta.name.unwrap();
+        assert_eq!((name.category(), name.tags()), ("anime", ["sticker".to_string()].as_slice()));
+
+        assert!(Metadata::read(&dir.path().join("missing.png")).is_err());
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,242 @@
+//! EXIF orientation
+//!
+//! Cameras store photos as taken and tag how they should be turned. The tag is applied when images
+//! are decoded for display, and can be baked into the pixels of sorted files with
+//! [Config::bake_orientation](crate::config::Config).
+//!
+//! Baking re-encodes the file, so the original is kept in [ORIGINALS_DIR] under the id of the move
+//! in the [Journal](crate::journal::Journal) and put back when that move is undone.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Cursor, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{DynamicImage, ImageError, ImageFormat, ImageOutputFormat};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Folder in the sorted folder with the original of every baked file
+pub const ORIGINALS_DIR: &str = ".image-sorter.originals";
+
+/// Quality of the JPEGs re-encoded by [bake]
+const JPEG_QUALITY: u8 = 95;
+
+/// JPEG markers
+const SOI: u8 = 0xd8;
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP15: u8 = 0xef;
+
+/// Tag number of the orientation in the TIFF structure of the EXIF data
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Transformation from the stored pixels to the upright image, in the order of the tag values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Mirrored along the top left to bottom right diagonal
+    Transpose,
+    /// Clockwise
+    Rotate90,
+    /// Mirrored along the top right to bottom left diagonal
+    Transverse,
+    Rotate270,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Orientation {
+    /// From the value of the EXIF tag, 1 to 8
+    pub fn from_exif(value: u32) -> Option<Self> {
+        Some(match value {
+            1 => Self::Normal,
+            2 => Self::FlipHorizontal,
+            3 => Self::Rotate180,
+            4 => Self::FlipVertical,
+            5 => Self::Transpose,
+            6 => Self::Rotate90,
+            7 => Self::Transverse,
+            8 => Self::Rotate270,
+            _ => return None,
+        })
+    }
+
+    /// Orientation of the file at `path`, [Orientation::Normal] if it has no valid tag
+    pub fn read(path: &Path) -> Self {
+        match File::open(path) {
+            Ok(file) => Self::from_container(&mut BufReader::new(file)),
+            Err(_) => Self::Normal,
+        }
+    }
+
+    /// Turn `img` upright
+    pub fn apply(self, img: DynamicImage) -> DynamicImage {
+        match self {
+            Self::Normal => img,
+            Self::FlipHorizontal => img.fliph(),
+            Self::Rotate180 => img.rotate180(),
+            Self::FlipVertical => img.flipv(),
+            Self::Transpose => img.rotate90().fliph(),
+            Self::Rotate90 => img.rotate90(),
+            Self::Transverse => img.rotate270().fliph(),
+            Self::Rotate270 => img.rotate270(),
+        }
+    }
+
+    // -- private items
+    fn from_container(reader: &mut (impl BufRead + Seek)) -> Self {
+        let Ok(exif) = exif::Reader::new().read_from_container(reader) else { return Self::Normal };
+
+        exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            .and_then(Self::from_exif)
+            .unwrap_or_default()
+    }
+}
+
+/// Where the original of the file baked by the move `id` in the journal of `dir` is kept
+pub fn original_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(ORIGINALS_DIR).join(id.to_string())
+}
+
+/// Turn the pixels of the JPEG at `path` upright, returns whether it had an orientation to apply
+///
+/// The original bytes are copied to `original` first. The image is re-encoded at [JPEG_QUALITY]
+/// and keeps the metadata of the original (EXIF, XMP, color profile), only the orientation tag is
+/// reset. Other formats are left as they are, they are still shown upright.
+pub fn bake(path: &Path, original: &Path) -> Result<bool, ImageError> {
+    let bytes = fs::read(path)?;
+    if image::guess_format(&bytes).ok() != Some(ImageFormat::Jpeg) {
+        return Ok(false);
+    }
+
+    let orientation = Orientation::from_container(&mut Cursor::new(&bytes));
+    if orientation == Orientation::Normal {
+        return Ok(false);
+    }
+
+    let img = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg)?;
+    let mut encoded = vec![];
+    orientation.apply(img).write_to(&mut encoded, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+    let baked = with_metadata(&encoded, &bytes);
+
+    if let Some(dir) = original.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_synced(original, &bytes)?;
+
+    // written next to the file and renamed over it, an error never leaves it half written
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.rotating"));
+
+    if let Err(e) = write_synced(&tmp, &baked).and_then(|()| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        let _ = fs::remove_file(original);
+        return Err(e.into());
+    }
+
+    Ok(true)
+}
+
+/// Put the original kept by [bake] back over the baked file at `path`
+///
+/// Returns whether there was one, nothing happens when `path` is gone.
+pub fn restore(original: &Path, path: &Path) -> io::Result<bool> {
+    if !original.exists() || !path.exists() {
+        return Ok(false);
+    }
+
+    fs::rename(original, path)?;
+    Ok(true)
+}
+
+// -- private items
+fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// The JPEG `encoded` with the metadata segments of the JPEG `original`, the EXIF orientation
+/// reset to upright
+fn with_metadata(encoded: &[u8], original: &[u8]) -> Vec<u8> {
+    let kept = app_segments(original).into_iter()
+        .filter(|(marker, _)| *marker != APP0)
+        .map(|(marker, segment)| {
+            let mut segment = segment.to_vec();
+            let exif = marker == APP1 && segment[4..].starts_with(b"Exif\0\0");
+            if exif && reset_orientation(&mut segment[10..]).is_none() {
+                eprintln!("orientation tag not found in the EXIF data");
+            }
+            segment
+        });
+
+    // after the start of image and the encoder's own JFIF segment
+    let split = app_segments(encoded).into_iter()
+        .take_while(|(marker, _)| *marker == APP0)
+        .map(|(_, segment)| segment.len())
+        .sum::<usize>() + 2;
+
+    let mut out = encoded[..split].to_vec();
+    kept.for_each(|segment| out.extend(segment));
+    out.extend(&encoded[split..]);
+    out
+}
+
+/// Application segments at the start of a JPEG, with their marker, up to the first other segment
+fn app_segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = vec![];
+    if jpeg.get(..2) != Some(&[0xff, SOI]) {
+        return segments;
+    }
+
+    let mut pos = 2;
+    while let Some(&[0xff, marker, hi, lo]) = jpeg.get(pos..pos + 4) {
+        if !(APP0..=APP15).contains(&marker) {
+            break;
+        }
+
+        let end = pos + 2 + usize::from(u16::from_be_bytes([hi, lo]));
+        let Some(segment) = jpeg.get(pos..end) else { break };
+        segments.push((marker, segment));
+        pos = end;
+    }
+
+    segments
+}
+
+/// Set the orientation tag of the first IFD of the TIFF structure `tiff` to upright
+fn reset_orientation(tiff: &mut [u8]) -> Option<()> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |tiff: &[u8], i: usize| {
+        let b = tiff.get(i..i + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+    let b = tiff.get(4..8)?.try_into().ok()?;
+    let ifd = usize::try_from(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }).ok()?;
+
+    let entry = (0..usize::from(u16_at(tiff, ifd)?))
+        .map(|n| ifd + 2 + n * 12)
+        .find(|&entry| u16_at(tiff, entry) == Some(ORIENTATION_TAG))?;
+
+    // a single SHORT, stored at the start of the value field
+    let one = if big_endian { 1u16.to_be_bytes() } else { 1u16.to_le_bytes() };
+    tiff.get_mut(entry + 8..entry + 10)?.copy_from_slice(&one);
+    Some(())
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
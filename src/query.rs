@@ -0,0 +1,496 @@
+//! Library queries
+//!
+//! Small query language over the [LibraryIndex](crate::index::LibraryIndex):
+//!
+//! - `anime`, `tag:evan*`: files with a matching tag or category, `*` and `?` are wildcards
+//! - `category:anime`: files with a matching category only
+//! - `size>2M`, `size<=500k`: file size, units are powers of 1024
+//! - `date>=2023-01`, `date=2023-01-10`: modification date (UTC), `YYYY`, `YYYY-MM` or `YYYY-MM-DD`
+//! - `a and b`, `a b`, `a or b`, `not a`, `(a or b) and c`
+//! - `"my tag"`, `category:"a b"`, `"or"`: double quotes keep spaces, parentheses and keywords
+//!
+//! `not` binds tighter than `and`, which binds tighter than `or`. Keywords are case-insensitive.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    date::{days_from_civil, days_in_month, SECONDS_PER_DAY},
+    index::Entry,
+};
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Parsed query, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    /// Glob matched against the category and tags
+    Tag(String),
+    /// Glob matched against the category
+    Category(String),
+    Size(Cmp, u64),
+    /// Modification time in a `[start, end)` range of unix seconds
+    Date(Cmp, i64, i64),
+}
+
+/// Comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+/// Recursive descent parser over the token list
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            Self::And(a, b) => a.matches(entry) && b.matches(entry),
+            Self::Or(a, b) => a.matches(entry) || b.matches(entry),
+            Self::Not(q) => !q.matches(entry),
+            Self::Tag(glob) => {
+                glob_match(glob, &entry.category) || entry.tags.iter().any(|t| glob_match(glob, t))
+            },
+            Self::Category(glob) => glob_match(glob, &entry.category),
+            Self::Size(cmp, size) => cmp.eval(entry.size, *size),
+            Self::Date(cmp, start, end) => {
+                let mtime = entry.mtime as i64;
+                match cmp {
+                    Cmp::Lt => mtime < *start,
+                    Cmp::Le => mtime < *end,
+                    Cmp::Gt => mtime >= *end,
+                    Cmp::Ge => mtime >= *start,
+                    Cmp::Eq => (*start..*end).contains(&mtime),
+                    Cmp::Ne => !(*start..*end).contains(&mtime),
+                }
+            },
+        }
+    }
+
+    /// Entries matching the query, in index order
+    pub fn filter<'e>(&'e self, entries: impl Iterator<Item = &'e Entry>) -> Vec<&'e Entry> {
+        entries.filter(|e| self.matches(e)).collect()
+    }
+}
+
+impl Cmp {
+    fn eval<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Self::Lt => a < b,
+            Self::Le => a <= b,
+            Self::Gt => a > b,
+            Self::Ge => a >= b,
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+
+        if parser.tokens.is_empty() {
+            return Err("empty query".to_string());
+        }
+
+        let query = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(query),
+            Some(t) => Err(format!("unexpected {t}")),
+        }
+    }
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+
+        while self.eat(&Token::Or) {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+
+        Ok(query)
+    }
+
+    /// Terms next to each other are joined with an implicit `and`
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                Some(Token::Word(_) | Token::Not | Token::Open) => {},
+                _ => break,
+            }
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.eat(&Token::Not) {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Open) => {
+                self.pos += 1;
+                let query = self.or()?;
+                if !self.eat(&Token::Close) {
+                    return Err("missing closing parenthesis".to_string());
+                }
+                Ok(query)
+            },
+            Some(Token::Word(w)) => {
+                self.pos += 1;
+                term(&w)
+            },
+            Some(t) => Err(format!("unexpected {t}")),
+            None => Err("unexpected end of query".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open => f.write_str("\"(\""),
+            Self::Close => f.write_str("\")\""),
+            Self::And => f.write_str("\"and\""),
+            Self::Or => f.write_str("\"or\""),
+            Self::Not => f.write_str("\"not\""),
+            Self::Word(w) => write!(f, "{w:?}"),
+        }
+    }
+}
+
+/// Case-insensitive glob match, `*` matches any run of characters and `?` a single one
+pub fn glob_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut g, mut t) = (0, 0);
+    // position of the last `*` and the text position it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            },
+            Some(c) if *c == '?' || *c == text[t] => {
+                g += 1;
+                t += 1;
+            },
+            _ => match star {
+                // let the last `*` eat one more character
+                Some((star_g, star_t)) => {
+                    star = Some((star_g, star_t + 1));
+                    g = star_g + 1;
+                    t = star_t + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+// -- private items
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    // a word with quotes in it is never a keyword, even if it's empty
+    let mut quoted = false;
+    let mut in_quotes = false;
+
+    let flush = |word: &mut String, quoted: &mut bool, tokens: &mut Vec<Token>| {
+        if word.is_empty() && !*quoted {
+            return;
+        }
+        tokens.push(match word.to_ascii_lowercase().as_str() {
+            "and" if !*quoted => Token::And,
+            "or" if !*quoted => Token::Or,
+            "not" if !*quoted => Token::Not,
+            _ => Token::Word(word.clone()),
+        });
+        word.clear();
+        *quoted = false;
+    };
+
+    for c in s.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                quoted = true;
+            },
+            c if in_quotes => word.push(c),
+            '(' | ')' => {
+                flush(&mut word, &mut quoted, &mut tokens);
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            },
+            c if c.is_whitespace() => flush(&mut word, &mut quoted, &mut tokens),
+            c => word.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("missing closing quote".to_string());
+    }
+    flush(&mut word, &mut quoted, &mut tokens);
+
+    Ok(tokens)
+}
+
+/// Single term: a tag glob, `key:glob` or `key<op>value`
+///
+/// Only `size`, `date` and `mtime` are compared, anything else with `<>=!` in it is a glob.
+fn term(word: &str) -> Result<Query, String> {
+    let comparison = word.find(['<', '>', '=', '!']).filter(|&op_start| {
+        matches!(word[..op_start].to_ascii_lowercase().as_str(), "size" | "date" | "mtime")
+    });
+
+    if let Some(op_start) = comparison {
+        let key = &word[..op_start];
+        let rest = &word[op_start..];
+
+        let (cmp, value) = [
+            ("<=", Cmp::Le), (">=", Cmp::Ge), ("!=", Cmp::Ne),
+            ("<", Cmp::Lt), (">", Cmp::Gt), ("=", Cmp::Eq),
+        ]
+            .into_iter()
+            .find_map(|(op, cmp)| rest.strip_prefix(op).map(|v| (cmp, v)))
+            .ok_or_else(|| format!("invalid comparison in {word:?}"))?;
+
+        return match key.to_ascii_lowercase().as_str() {
+            "size" => Ok(Query::Size(cmp, parse_size(value)?)),
+            "date" | "mtime" => {
+                let (start, end) = parse_date(value)?;
+                Ok(Query::Date(cmp, start, end))
+            },
+            _ => unreachable!("only size and date keys are compared"),
+        };
+    }
+
+    if let Some((key, glob)) = word.split_once(':') {
+        if glob.is_empty() {
+            return Err(format!("missing value in {word:?}"));
+        }
+
+        return match key.to_ascii_lowercase().as_str() {
+            "tag" => Ok(Query::Tag(glob.to_string())),
+            "category" | "cat" => Ok(Query::Category(glob.to_string())),
+            _ => Err(format!("unknown field {key:?} in {word:?}, expected tag or category")),
+        };
+    }
+
+    Ok(Query::Tag(word.to_string()))
+}
+
+/// `<number>[unit]` with units `b`, `k`, `m` or `g`, optionally followed by `b` or `ib`
+fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| format!("invalid size {s:?}"))?;
+    let unit = unit.to_ascii_lowercase();
+    let unit = unit.trim_end_matches("ib").trim_end_matches('b');
+
+    let multiplier = match unit {
+        "" => 1u64,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return Err(format!("unknown size unit in {s:?}")),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// `[start, end)` of a `YYYY`, `YYYY-MM` or `YYYY-MM-DD` date in unix seconds
+fn parse_date(s: &str) -> Result<(i64, i64), String> {
+    let invalid = || format!("invalid date {s:?}, expected YYYY, YYYY-MM or YYYY-MM-DD");
+
+    let parts = s.split('-')
+        .map(|p| p.parse::<i64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (start, end) = match parts[..] {
+        [y] => (days_from_civil(y, 1, 1), days_from_civil(y + 1, 1, 1)),
+        [y, m] if (1..=12).contains(&m) => {
+            let next = if m == 12 { (y + 1, 1) } else { (y, m + 1) };
+            (days_from_civil(y, m, 1), days_from_civil(next.0, next.1, 1))
+        },
+        [y, m, d] if (1..=12).contains(&m) && (1..=days_in_month(y, m)).contains(&d) => {
+            let day = days_from_civil(y, m, d);
+            (day, day + 1)
+        },
+        _ => return Err(invalid()),
+    };
+
+    Ok((start * SECONDS_PER_DAY, end * SECONDS_PER_DAY))
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Query {
+        s.parse().unwrap()
+    }
+
+    fn tag(glob: &str) -> Box<Query> {
+        Box::new(Query::Tag(glob.to_string()))
+    }
+
+    fn entry(category: &str, tags: &[&str], mtime: u64) -> Entry {
+        Entry {
+            path: "x.png".into(),
+            category: category.to_string(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            size: 0,
+            mtime,
+            hash: String::new(),
+        }
+    }
+
+    /// Unix seconds at the start of a day
+    fn day(y: i64, m: i64, d: i64) -> u64 {
+        (days_from_civil(y, m, d) * SECONDS_PER_DAY) as u64
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        assert_eq!(
+            parse("a or b and not c"),
+            Query::Or(tag("a"), Box::new(Query::And(tag("b"), Box::new(Query::Not(tag("c")))))),
+        );
+        assert_eq!(parse("a b OR c"), parse("(a and b) or c"));
+        assert_eq!(
+            parse("a (b or c)"),
+            Query::And(tag("a"), Box::new(Query::Or(tag("b"), tag("c")))),
+        );
+        assert_eq!(parse("not not a"), Query::Not(Box::new(Query::Not(tag("a")))));
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        for query in ["", "a or", "(a", "a)", "not", "size>", "date=2023-13", "tag:"] {
+            assert!(query.parse::<Query>().is_err(), "{query:?}");
+        }
+    }
+
+    #[test]
+    fn quotes_keep_spaces_and_keywords() {
+        assert_eq!(parse("\"my tag\""), *tag("my tag"));
+        assert_eq!(parse("\"or\" and \"(x)\""), Query::And(tag("or"), tag("(x)")));
+        assert_eq!(parse("category:\"a b\""), Query::Category("a b".to_string()));
+        assert!("\"open".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn only_known_keys_are_compared() {
+        assert_eq!(parse("size>=2k"), Query::Size(Cmp::Ge, 2048));
+        assert_eq!(parse("SIZE<1.5M"), Query::Size(Cmp::Lt, 3 << 19));
+        assert_eq!(parse("<3"), *tag("<3"));
+        assert_eq!(parse("a=b"), *tag("a=b"));
+        assert_eq!(parse("tag:x!=y"), *tag("x!=y"));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("evan*", "Evangelion"));
+        assert!(glob_match("*gel*", "evangelion"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(!glob_match("anime", "anime2"));
+
+        let e = entry("anime", &["sticker", "evangelion"], 0);
+        assert!(parse("evan*").matches(&e));
+        assert!(parse("anim?").matches(&e));
+        assert!(!parse("category:stick*").matches(&e));
+        assert!(parse("not meme").matches(&e));
+    }
+
+    #[test]
+    fn date_ranges_end_with_the_period() {
+        let jan_end = entry("", &[], day(2023, 2, 1) - 1);
+        let feb = entry("", &[], day(2023, 2, 1));
+
+        assert!(parse("date=2023-01").matches(&jan_end));
+        assert!(parse("date<=2023-01").matches(&jan_end));
+        assert!(!parse("date<=2023-01").matches(&feb));
+        assert!(parse("date>2023-01").matches(&feb));
+        assert!(parse("mtime=2023").matches(&feb));
+        assert!(parse("date=2023-01-31").matches(&jan_end));
+        assert!(parse("date!=2023-01-31").matches(&feb));
+
+        let dec_end = entry("", &[], day(2024, 1, 1) - 1);
+        assert!(parse("date=2023-12-31").matches(&dec_end));
+        assert!(parse("date<2024").matches(&dec_end));
+    }
+
+    #[test]
+    fn leap_years() {
+        assert_eq!(parse_date("2024-02").unwrap().1, day(2024, 3, 1) as i64);
+        assert_eq!(parse_date("2024-02-29").unwrap().0, day(2024, 2, 29) as i64);
+        assert_eq!(parse_date("2000-02-29").unwrap().1, day(2000, 3, 1) as i64);
+
+        for date in ["2023-02-29", "1900-02-29", "2023-04-31", "2023-01-32", "2023-00"] {
+            assert!(parse_date(date).is_err(), "{date}");
+        }
+
+        let leap_day = entry("", &[], day(2024, 2, 29) + 3600);
+        assert!(parse("date=2024-02").matches(&leap_day));
+        assert!(!parse("date>=2024-03").matches(&leap_day));
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
@@ -0,0 +1,508 @@
+//! Sorting queue
+//!
+//! The files waiting to be sorted in a folder and the moves done on them, with undo/redo through
+//! the [History], crash safety through the [Journal] and the [LibraryIndex] kept up to date.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    collections::BTreeSet,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use crate::config::{CollisionPolicy, Config, CONFIG_FILE_STEM};
+use crate::error::Error;
+use crate::file_store::{self, Resolution};
+use crate::format::ImageFormat;
+use crate::history::{Action, History};
+use crate::index::{LibraryIndex, ScanStats};
+use crate::journal::Journal;
+use crate::orientation;
+use crate::sorted_name::SortedName;
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Moves between two saves of the library index, it is also saved when the queue is dropped
+///
+/// The index is only a cache, anything lost is picked up by the next scan.
+pub const INDEX_SAVE_INTERVAL: usize = 16;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Result of [SortQueue::move_current]
+#[derive(Debug, Clone)]
+pub enum MoveOutcome {
+    /// The file was moved to this path
+    Moved(PathBuf),
+    /// An identical file already exists, the file was left in place and the queue advanced
+    Skipped { existing: PathBuf },
+    /// A different file already exists and the policy is to ask, nothing was done
+    Conflict { existing: PathBuf },
+}
+
+/// Why a file of the folder is not in the queue
+#[derive(Debug, Clone)]
+pub enum SkipReason {
+    /// Not an image format that can be shown
+    Unsupported,
+    /// An image format left out of [Config::formats]
+    Disabled(ImageFormat),
+    /// The file can't be read
+    Unreadable(String),
+}
+
+/// File left out of the queue
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    pub name: OsString,
+    pub reason: SkipReason,
+}
+
+/// Files of a folder, see [SortQueue::get_file_list]
+#[derive(Debug, Clone, Default)]
+pub struct FileList {
+    /// Images to sort, by name
+    pub files: Vec<OsString>,
+    /// Everything else, by name
+    pub skipped: Vec<Skipped>,
+}
+
+/// Files left to sort in a folder
+pub struct SortQueue {
+    pub image_index: usize,
+    /// File names, not necessarily valid unicode
+    pub images: Vec<OsString>,
+
+    total_file_count: usize,
+    dir: PathBuf,
+    history: History,
+    journal: Journal,
+    index: LibraryIndex,
+    skipped: Vec<Skipped>,
+    /// See [Config::bake_orientation]
+    bake_orientation: bool,
+    /// Moves recorded in the index since it was last saved
+    unsaved_moves: usize,
+    /// Scan started by [SortQueue::reindex] that didn't finish yet
+    scan: Option<Scan>,
+}
+
+/// Library scan running in the background on a copy of the index
+struct Scan {
+    result: mpsc::Receiver<(LibraryIndex, io::Result<ScanStats>)>,
+    /// Moves done since the scan started, recorded again in the scanned index
+    moves: Vec<(PathBuf, PathBuf)>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl SortQueue {
+    /// Open the queue of `images_path`, finishing any move interrupted in a previous session
+    pub fn new(images_path: &Path, config: &Config) -> Result<Self, Error> {
+        // absolute, so journal entries stay valid when started from another directory
+        let dir = images_path.canonicalize()
+            .map_err(|e| Error::OpenFolder(images_path.to_path_buf(), e))?;
+        println!("images path: {dir:?}");
+
+        // finish anything left over from an interrupted session before listing the files
+        if let Err(e) = Journal::recover(&dir) {
+            eprintln!("failed to recover interrupted moves: {e}");
+        }
+
+        let journal = Journal::open(&dir).map_err(|e| Error::OpenFolder(dir.clone(), e))?;
+
+        // the index is only a cache of the sorted folders, a broken one is rebuilt
+        let index = LibraryIndex::open(&dir).unwrap_or_else(|e| {
+            eprintln!("failed to load library index, rebuilding it: {e}");
+            LibraryIndex::empty(&dir)
+        });
+
+        let FileList { files: images, skipped } = Self::get_file_list(&dir, &config.formats)
+            .map_err(|e| Error::OpenFolder(dir.clone(), e))?;
+        println!("file count: {}", images.len());
+        for s in &skipped {
+            println!("skipped {:?}: {}", s.name, s.reason);
+        }
+
+        Ok(Self {
+            image_index: 0,
+            total_file_count: images.len(),
+            history: History::default(),
+            journal,
+            index,
+            dir,
+            images,
+            skipped,
+            bake_orientation: config.bake_orientation,
+            unsaved_moves: 0,
+            scan: None,
+        })
+    }
+
+    /// Create the destination folder of every button and the default folder
+    pub fn create_output_dirs(&self, config: &Config) -> Result<(), Error> {
+        let i = config.buttons.values()
+            .map(|b_cfg| b_cfg.path.as_str())
+            .chain(std::iter::once(config.default_folder.as_str()));
+
+        for path in i {
+            let dir = self.dir.join(path);
+            fs::create_dir_all(&dir).map_err(|e| Error::CreateDir(dir, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Follow [Config::bake_orientation] after the config changed
+    pub fn set_bake_orientation(&mut self, bake: bool) {
+        self.bake_orientation = bake;
+    }
+
+    /// Rescan the destination folders of `config` into the library index
+    ///
+    /// Hashing the new files can take a while, the scan runs on its own thread and the index is
+    /// replaced once [Self::poll_reindex] sees it finish. A scan still running is abandoned.
+    pub fn reindex(&mut self, config: &Config) {
+        let mut index = self.index.clone();
+        let folders = LibraryIndex::folders(config);
+        let (sender, result) = mpsc::channel();
+
+        let spawned = thread::Builder::new()
+            .name("reindex".to_string())
+            .spawn(move || {
+                let stats = index.scan(folders);
+                let _ = sender.send((index, stats));
+            });
+
+        match spawned {
+            Ok(_) => self.scan = Some(Scan { result, moves: vec![] }),
+            Err(e) => eprintln!("failed to start the library scan: {e}"),
+        }
+    }
+
+    /// Pick up the index scanned by [Self::reindex], returns whether it finished
+    pub fn poll_reindex(&mut self) -> bool {
+        let Some(scan) = &self.scan else { return false };
+
+        let (mut index, stats) = match scan.result.try_recv() {
+            Ok(res) => res,
+            Err(mpsc::TryRecvError::Empty) => return false,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                eprintln!("the library scan stopped unexpectedly");
+                self.scan = None;
+                return false;
+            },
+        };
+        let Some(scan) = self.scan.take() else { return false };
+
+        match stats {
+            Ok(stats) => println!("library index: {stats:?}"),
+            Err(e) => {
+                eprintln!("failed to scan the library: {e}");
+                return true;
+            },
+        }
+
+        // a file moved again since is recorded by its later move
+        for (source, destination) in &scan.moves {
+            match index.record_move(source, destination) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    eprintln!("failed to index {destination:?}: {e}");
+                },
+                _ => {},
+            }
+        }
+
+        self.index = index;
+        self.save_index();
+        true
+    }
+
+    pub fn index(&self) -> &LibraryIndex {
+        &self.index
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Files of the folder that were left out of the queue when it was opened
+    pub fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    pub fn get_total_files(&self) -> usize {
+        self.total_file_count
+    }
+
+    pub fn get_images_len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    pub fn get_current_pos(&self) -> usize {
+        self.image_index
+    }
+
+    /// `None` once everything is sorted
+    pub fn get_current_path(&self) -> Option<PathBuf> {
+        self.get_current_filename().map(|f| self.dir.join(f))
+    }
+
+    pub fn get_current_filename(&self) -> Option<&OsStr> {
+        self.images.get(self.image_index).map(OsString::as_os_str)
+    }
+
+    /// Current file name for display, invalid unicode is replaced
+    pub fn get_current_display_name(&self) -> Option<String> {
+        self.get_current_filename().map(|f| f.to_string_lossy().into_owned())
+    }
+
+    pub fn next_image(&mut self) {
+        let max = self.images.len().saturating_sub(1);
+        self.image_index += if self.image_index >= max { 0 } else { 1 };
+    }
+
+    pub fn prev_image(&mut self) {
+        self.image_index -= if self.image_index == 0 { 0 } else { 1 };
+    }
+
+    pub fn seek_to_image(&mut self, pos: usize) {
+        let max = self.images.len().saturating_sub(1);
+
+        self.image_index = if pos >= max { max } else { pos };
+    }
+
+    /// Path is prepended with no extra tokens so save can handle both separate and regular save
+    ///
+    /// Category is essentially the destination folder. On error the file and the queue are left
+    /// as they were.
+    pub fn move_current(
+        &mut self,
+        category: &str,
+        new_name: &str,
+        collision: CollisionPolicy
+    ) -> Result<MoveOutcome, Error> {
+
+        // TODO: make category a enum? it's the destination folder
+
+        let Some(f) = self.images.get(self.image_index) else { return Err(Error::EmptyQueue) };
+        let source_f = self.dir.join(f);
+
+        // invalid unicode in the original name is replaced, sorted names are always valid
+        let sorted_name = SortedName::from_typed(new_name, &f.to_string_lossy());
+        let output_path = self.dir.join(category).join(sorted_name.to_string());
+
+        let resolution = file_store::resolve_collision(&source_f, &output_path, collision)
+            .map_err(|error| Error::Move { from: source_f.clone(), to: output_path.clone(), error })?;
+
+        let output_path = match resolution {
+            Resolution::Free(p) => p,
+            Resolution::Identical(existing) => {
+                self.next_image();
+                return Ok(MoveOutcome::Skipped { existing });
+            },
+            Resolution::Conflict(existing) => return Ok(MoveOutcome::Conflict { existing }),
+        };
+
+        let journal_id = self.move_file(&source_f, &output_path, None)?;
+        if self.bake_orientation {
+            self.apply_orientation(&output_path, journal_id);
+        }
+
+        self.history.push(Action {
+            source: source_f,
+            destination: output_path.clone(),
+            index: self.image_index,
+            typed_name: new_name.to_string(),
+            journal_id,
+        });
+
+        self.remove_current();
+        Ok(MoveOutcome::Moved(output_path))
+    }
+
+    /// Undo the last move, putting the file back at its old queue position
+    ///
+    /// A file rotated by [Config::bake_orientation] gets its original contents back. Returns the
+    /// filename that was typed for it
+    pub fn undo(&mut self) -> Result<Option<String>, Error> {
+        let Some(action) = self.history.pop_undo() else { return Ok(None) };
+
+        let original = orientation::original_path(&self.dir, action.journal_id);
+        if let Err(error) = orientation::restore(&original, &action.destination) {
+            let e = Error::Move { from: original, to: action.destination.clone(), error };
+            self.history.push_redone(action);
+            return Err(e);
+        }
+
+        if let Err(e) = self.move_file(&action.destination, &action.source, Some(action.journal_id)) {
+            self.history.push_redone(action);
+            return Err(e);
+        }
+
+        let filename = action.source.file_name().unwrap_or_default().to_os_string();
+        self.image_index = action.index.min(self.images.len());
+        self.images.insert(self.image_index, filename);
+
+        let typed_name = action.typed_name.clone();
+        self.history.push_undone(action);
+
+        Ok(Some(typed_name))
+    }
+
+    /// Redo the last undone move
+    pub fn redo(&mut self) -> Result<(), Error> {
+        let Some(mut action) = self.history.pop_redo() else { return Ok(()) };
+
+        let filename = action.source.file_name();
+        let Some(pos) = self.images.iter().position(|f| Some(f.as_os_str()) == filename) else {
+            eprintln!("cannot redo, {:?} is no longer in the queue", action.source);
+            return Ok(());
+        };
+
+        // a new move, undoing it later reverts this one and not the original
+        match self.move_file(&action.source, &action.destination, None) {
+            Ok(id) => action.journal_id = id,
+            Err(e) => {
+                self.history.push_undone(action);
+                return Err(e);
+            },
+        }
+        if self.bake_orientation {
+            self.apply_orientation(&action.destination, action.journal_id);
+        }
+
+        self.image_index = pos;
+        self.remove_current();
+        self.history.push_redone(action);
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    /// Images of one of `formats` directly inside `dir`, and the files skipped
+    ///
+    /// Both lists are sorted by name, so the queue has the same order on every run instead of the
+    /// order of the directory listing.
+    pub fn get_file_list<P>(dir: P, formats: &BTreeSet<ImageFormat>) -> io::Result<FileList>
+    where P: AsRef<Path> {
+        let mut list = FileList::default();
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let filename = entry.file_name();
+
+            // folders and the files of the sorter itself aren't worth reporting
+            let own_file = filename.as_encoded_bytes().starts_with(CONFIG_FILE_STEM.as_bytes());
+            if !entry.file_type()?.is_file() || own_file {
+                continue;
+            }
+
+            let reason = match ImageFormat::detect(&entry.path()) {
+                Ok(Some(format)) if formats.contains(&format) => {
+                    list.files.push(filename);
+                    continue;
+                },
+                Ok(Some(format)) => SkipReason::Disabled(format),
+                Ok(None) => SkipReason::Unsupported,
+                Err(e) => SkipReason::Unreadable(e.to_string()),
+            };
+            list.skipped.push(Skipped { name: filename, reason });
+        }
+
+        list.files.sort();
+        list.skipped.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+
+    // -- private items
+    /// Move a file through the journal and update the library index, see [Journal::move_file]
+    fn move_file(&mut self, source: &Path, destination: &Path, reverts: Option<u64>) -> Result<u64, Error> {
+        let id = self.journal.move_file(source, destination, reverts).map_err(|error| Error::Move {
+            from: source.to_path_buf(),
+            to: destination.to_path_buf(),
+            error,
+        })?;
+
+        self.record_move(source, destination);
+        Ok(id)
+    }
+
+    /// Turn the file sorted by the move `journal_id` upright, a failure leaves it as it was
+    fn apply_orientation(&mut self, path: &Path, journal_id: u64) {
+        match orientation::bake(path, &orientation::original_path(&self.dir, journal_id)) {
+            Ok(true) => {
+                println!("applied the orientation of {path:?}");
+
+                // the size changed
+                self.record_move(path, path);
+            },
+            Ok(false) => {},
+            Err(e) => eprintln!("failed to apply the orientation of {path:?}: {e}"),
+        }
+    }
+
+    /// Update the library index, saving it every [INDEX_SAVE_INTERVAL] moves
+    fn record_move(&mut self, source: &Path, destination: &Path) {
+        if let Err(e) = self.index.record_move(source, destination) {
+            eprintln!("failed to index {destination:?}: {e}");
+        }
+        if let Some(scan) = &mut self.scan {
+            scan.moves.push((source.to_path_buf(), destination.to_path_buf()));
+        }
+
+        self.unsaved_moves += 1;
+        if self.unsaved_moves >= INDEX_SAVE_INTERVAL {
+            self.save_index();
+        }
+    }
+
+    fn save_index(&mut self) {
+        self.unsaved_moves = 0;
+        if let Err(e) = self.index.save() {
+            eprintln!("failed to save library index: {e}");
+        }
+    }
+
+    /// Drop the current image from the queue
+    fn remove_current(&mut self) {
+        self.images.remove(self.image_index);
+        self.image_index = self.image_index.min(self.images.len().saturating_sub(1));
+    }
+}
+
+impl Drop for SortQueue {
+    fn drop(&mut self) {
+        if self.unsaved_moves > 0 {
+            self.save_index();
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported => write!(f, "not a supported image"),
+            Self::Disabled(format) => write!(f, "{format} is not in the configured formats"),
+            Self::Unreadable(e) => write!(f, "cannot read it: {e}"),
+        }
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
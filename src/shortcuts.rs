@@ -0,0 +1,495 @@
+//! Keyboard shortcuts
+//!
+//! Key combinations are written emacs-style: `C-d`, `A-S-Left`, `Enter`. Every button gets a
+//! `C-<id>` shortcut derived from its id in [Config::buttons] unless it sets its own, ids longer
+//! than one character need an explicit shortcut.
+//!
+//! In fast sort (idle) mode the same keys work without modifiers, together with the single key
+//! commands of [Config::fast_sort]. A button whose id is one of those keys keeps its regular
+//! shortcut only, see [Bindings::warnings].
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Keys that can be bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// Letter or digit, letters are always lowercase
+    Char(char),
+    Left,
+    Right,
+    Up,
+    Down,
+    Enter,
+    Escape,
+    Tab,
+    Space,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+}
+
+/// A key together with its modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyCombo {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: Key,
+}
+
+/// Action triggered by a shortcut
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Command {
+    /// Button by its id in [Config::buttons]
+    Button(String),
+    Next,
+    Prev,
+    Undo,
+    Redo,
+    /// Open the current file in the default program
+    Open,
+    /// Move to the default folder using the typed filename
+    Confirm,
+    /// Switch to input mode, focusing the filename box
+    EnterInput,
+    /// Switch back to idle (fast sort) mode
+    EnterIdle,
+    /// Zoom modes of the shown image, see [viewport](crate::viewport)
+    ZoomFit,
+    ZoomFillWidth,
+    ZoomActual,
+    ZoomIn,
+    ZoomOut,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+}
+
+/// Two commands bound to the same keys
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub combo: KeyCombo,
+    pub first: Command,
+    pub second: Command,
+    /// Whether the conflict is between the idle mode keys
+    pub idle: bool,
+}
+
+/// Lookup table from key combination to command
+#[derive(Debug, Clone, Default)]
+pub struct Bindings {
+    by_combo: HashMap<KeyCombo, Command>,
+    by_command: HashMap<Command, KeyCombo>,
+
+    /// Keys only active in idle mode
+    idle_by_combo: HashMap<KeyCombo, Command>,
+    idle_by_command: HashMap<Command, KeyCombo>,
+
+    /// Buttons left out of idle mode because their key is a fast sort command
+    warnings: Vec<Conflict>,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Bindings {
+    /// Build the bindings for `config`, reporting every conflict found
+    pub fn new(config: &Config) -> Result<Self, Vec<Conflict>> {
+        let mut bindings = Self::default();
+        let mut errors = vec![];
+
+        let sc = &config.shortcuts;
+        let fixed = [
+            (sc.next, Command::Next),
+            (sc.prev, Command::Prev),
+            (sc.undo, Command::Undo),
+            (sc.redo, Command::Redo),
+            (sc.open, Command::Open),
+            (sc.confirm, Command::Confirm),
+            (sc.zoom_fit, Command::ZoomFit),
+            (sc.zoom_fill_width, Command::ZoomFillWidth),
+            (sc.zoom_actual, Command::ZoomActual),
+            (sc.zoom_in, Command::ZoomIn),
+            (sc.zoom_out, Command::ZoomOut),
+            (sc.pan_up, Command::PanUp),
+            (sc.pan_down, Command::PanDown),
+            (sc.pan_left, Command::PanLeft),
+            (sc.pan_right, Command::PanRight),
+            (config.fast_sort.idle, Command::EnterIdle),
+        ];
+
+        let fs = &config.fast_sort;
+        let idle_fixed = [
+            (fs.input, Command::EnterInput),
+            (fs.next, Command::Next),
+            (fs.prev, Command::Prev),
+            (fs.undo, Command::Undo),
+            (fs.redo, Command::Redo),
+            (fs.open, Command::Open),
+            (fs.zoom_in, Command::ZoomIn),
+            (fs.zoom_out, Command::ZoomOut),
+            (fs.pan_up, Command::PanUp),
+            (fs.pan_down, Command::PanDown),
+            (fs.pan_left, Command::PanLeft),
+            (fs.pan_right, Command::PanRight),
+        ];
+
+        // buttons with longer ids and no explicit shortcut are left unbound
+        let buttons: Vec<_> = config.buttons.iter().filter_map(|(id, b_cfg)| {
+            b_cfg.shortcut
+                .or_else(|| KeyCombo::for_button(id))
+                .map(|combo| (combo, Command::Button(id.clone())))
+        }).collect();
+
+        // in idle mode buttons use the same key without modifiers
+        let idle_buttons: Vec<_> = buttons.iter()
+            .map(|(combo, command)| (KeyCombo::new(combo.key), command.clone()))
+            .collect();
+
+        for (combo, command) in fixed.into_iter().chain(buttons) {
+            if let Err(c) = Self::bind(&mut bindings.by_combo, &mut bindings.by_command, combo, command) {
+                errors.push(c);
+            }
+        }
+
+        for (combo, command) in idle_fixed.into_iter().chain(idle_buttons) {
+            let by_combo = &mut bindings.idle_by_combo;
+            if let Err(mut c) = Self::bind(by_combo, &mut bindings.idle_by_command, combo, command) {
+                c.idle = true;
+                // button ids predate fast sort mode, configs using its keys as ids keep loading
+                let shadowed = matches!(c.second, Command::Button(_))
+                    && !matches!(c.first, Command::Button(_));
+                if shadowed {
+                    bindings.warnings.push(c);
+                } else {
+                    errors.push(c);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(bindings)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn command(&self, combo: &KeyCombo) -> Option<&Command> {
+        self.by_combo.get(combo)
+    }
+
+    pub fn combo(&self, command: &Command) -> Option<&KeyCombo> {
+        self.by_command.get(command)
+    }
+
+    /// Command bound to `combo` in idle mode, falling back to the regular bindings
+    pub fn idle_command(&self, combo: &KeyCombo) -> Option<&Command> {
+        self.idle_by_combo.get(combo).or_else(|| self.command(combo))
+    }
+
+    /// Buttons whose id is taken by a fast sort command, they only have their regular shortcut in
+    /// idle mode
+    pub fn warnings(&self) -> &[Conflict] {
+        &self.warnings
+    }
+
+    /// `label [combo]`, or just the label when the command is unbound
+    pub fn label(&self, label: &str, command: &Command) -> String {
+        match self.combo(command) {
+            Some(combo) => format!("{label} [{combo}]"),
+            None => label.to_string(),
+        }
+    }
+
+    /// Same as [Bindings::label] showing the idle mode key if there is one
+    pub fn idle_label(&self, label: &str, command: &Command) -> String {
+        match self.idle_by_command.get(command) {
+            Some(combo) => format!("{label} [{combo}]"),
+            None => self.label(label, command),
+        }
+    }
+
+    // -- private items
+    fn bind(
+        by_combo: &mut HashMap<KeyCombo, Command>,
+        by_command: &mut HashMap<Command, KeyCombo>,
+        combo: KeyCombo,
+        command: Command,
+    ) -> Result<(), Conflict> {
+        if let Some(first) = by_combo.get(&combo) {
+            return Err(Conflict { combo, first: first.clone(), second: command, idle: false });
+        }
+
+        by_command.insert(command.clone(), combo);
+        by_combo.insert(combo, command);
+        Ok(())
+    }
+}
+
+impl KeyCombo {
+    pub const fn new(key: Key) -> Self {
+        Self { ctrl: false, alt: false, shift: false, key }
+    }
+
+    pub const fn ctrl(key: Key) -> Self {
+        Self { ctrl: true, alt: false, shift: false, key }
+    }
+
+    pub const fn alt(key: Key) -> Self {
+        Self { ctrl: false, alt: true, shift: false, key }
+    }
+
+    /// `C-<id>` for single character button ids
+    pub fn for_button(id: &str) -> Option<Self> {
+        let mut chars = id.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphanumeric() => {
+                Some(Self::ctrl(Key::Char(c.to_ascii_lowercase())))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Key {
+    const NAMES: [(Key, &'static str); 15] = [
+        (Key::Left, "Left"),
+        (Key::Right, "Right"),
+        (Key::Up, "Up"),
+        (Key::Down, "Down"),
+        (Key::Enter, "Enter"),
+        (Key::Escape, "Escape"),
+        (Key::Tab, "Tab"),
+        (Key::Space, "Space"),
+        (Key::Backspace, "Backspace"),
+        (Key::Delete, "Delete"),
+        (Key::Insert, "Insert"),
+        (Key::Home, "Home"),
+        (Key::End, "End"),
+        (Key::PageUp, "PageUp"),
+        (Key::PageDown, "PageDown"),
+    ];
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return if c.is_ascii_alphanumeric() {
+                Ok(Key::Char(c.to_ascii_lowercase()))
+            } else {
+                Err(format!("unsupported key {c:?}"))
+            };
+        }
+
+        Self::NAMES.iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|(key, _)| *key)
+            .ok_or_else(|| format!("unknown key {s:?}"))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Char(c) => write!(f, "{c}"),
+            key => {
+                let (_, name) = Self::NAMES.iter()
+                    .find(|(k, _)| k == key)
+                    .expect("every named key is listed");
+                f.write_str(name)
+            },
+        }
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    /// Parse `[C-][A-][S-]<key>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut combo = KeyCombo::new(Key::Space);
+        let mut rest = s.trim();
+
+        while let Some((modifier, tail)) = rest.split_once('-').filter(|(_, t)| !t.is_empty()) {
+            match modifier {
+                "C" => combo.ctrl = true,
+                "A" => combo.alt = true,
+                "S" => combo.shift = true,
+                _ => return Err(format!("unknown modifier {modifier:?} in {s:?}")),
+            }
+            rest = tail;
+        }
+
+        combo.key = rest.parse()?;
+        Ok(combo)
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            f.write_str("C-")?;
+        }
+        if self.alt {
+            f.write_str("A-")?;
+        }
+        if self.shift {
+            f.write_str("S-")?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+impl TryFrom<String> for KeyCombo {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<KeyCombo> for String {
+    fn from(value: KeyCombo) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Button(id) => write!(f, "button {id:?}"),
+            Command::Next => f.write_str("next"),
+            Command::Prev => f.write_str("prev"),
+            Command::Undo => f.write_str("undo"),
+            Command::Redo => f.write_str("redo"),
+            Command::Open => f.write_str("open"),
+            Command::Confirm => f.write_str("confirm"),
+            Command::EnterInput => f.write_str("input mode"),
+            Command::EnterIdle => f.write_str("idle mode"),
+            Command::ZoomFit => f.write_str("zoom to fit"),
+            Command::ZoomFillWidth => f.write_str("zoom to fill width"),
+            Command::ZoomActual => f.write_str("zoom to actual size"),
+            Command::ZoomIn => f.write_str("zoom in"),
+            Command::ZoomOut => f.write_str("zoom out"),
+            Command::PanUp => f.write_str("pan up"),
+            Command::PanDown => f.write_str("pan down"),
+            Command::PanLeft => f.write_str("pan left"),
+            Command::PanRight => f.write_str("pan right"),
+        }
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.idle { " in idle mode" } else { "" };
+        write!(f, "{} is bound to both {} and {}{mode}", self.combo, self.first, self.second)
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ButtonConfig, DEFAULT_CONFIG};
+
+    fn with_button(id: &str, shortcut: Option<&str>) -> Config {
+        let mut config = DEFAULT_CONFIG.clone();
+        config.buttons.insert(id.to_string(), ButtonConfig {
+            label: id.to_string(),
+            button_label: id.to_string(),
+            path: id.to_string(),
+            shortcut: shortcut.map(|s| s.parse().unwrap()),
+        });
+        config
+    }
+
+    #[test]
+    fn parse() {
+        let combo = |s: &str| s.parse::<KeyCombo>();
+
+        assert_eq!(combo("d"), Ok(KeyCombo::new(Key::Char('d'))));
+        assert_eq!(combo("C-D"), Ok(KeyCombo::ctrl(Key::Char('d'))));
+        assert_eq!(combo(" A-pageup "), Ok(KeyCombo::alt(Key::PageUp)));
+        assert_eq!(
+            combo("C-A-S-Left"),
+            Ok(KeyCombo { ctrl: true, alt: true, shift: true, key: Key::Left }),
+        );
+        assert!(combo("").is_err());
+        assert!(combo("C-").is_err());
+        assert!(combo("X-a").is_err());
+        assert!(combo("C-F13").is_err());
+        assert!(combo("C-?").is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in ["d", "0", "C-z", "A-S-Left", "C-A-S-PageDown", "Enter", "S-u"] {
+            let combo: KeyCombo = s.parse().unwrap();
+            assert_eq!(combo.to_string(), s);
+            assert_eq!(combo.to_string().parse(), Ok(combo));
+        }
+
+        for (key, _) in Key::NAMES {
+            assert_eq!(key.to_string().parse(), Ok(key));
+        }
+    }
+
+    #[test]
+    fn fast_sort_keys_shadow_buttons_in_idle_mode() {
+        let bindings = Bindings::new(&with_button("u", None)).unwrap();
+        let u = Command::Button("u".to_string());
+
+        assert!(matches!(bindings.warnings(), [Conflict { idle: true, first: Command::Undo, .. }]));
+        assert_eq!(bindings.idle_command(&KeyCombo::new(Key::Char('u'))), Some(&Command::Undo));
+        assert_eq!(bindings.idle_command(&KeyCombo::ctrl(Key::Char('u'))), Some(&u));
+        assert_eq!(bindings.idle_label("U", &u), "U [C-u]");
+
+        assert!(Bindings::new(&DEFAULT_CONFIG).unwrap().warnings().is_empty());
+    }
+
+    #[test]
+    fn button_shortcuts() {
+        assert_eq!(KeyCombo::for_button("D"), Some(KeyCombo::ctrl(Key::Char('d'))));
+        assert_eq!(KeyCombo::for_button("ab"), None);
+        assert_eq!(KeyCombo::for_button("-"), None);
+
+        let bindings = Bindings::new(&with_button("long", Some("A-l"))).unwrap();
+        let long = Command::Button("long".to_string());
+        let d = Command::Button("d".to_string());
+        assert_eq!(bindings.command(&KeyCombo::ctrl(Key::Char('d'))), Some(&d));
+        assert_eq!(bindings.combo(&long), Some(&KeyCombo::alt(Key::Char('l'))));
+        assert_eq!(bindings.idle_command(&KeyCombo::new(Key::Char('l'))), Some(&long));
+        assert_eq!(bindings.idle_command(&KeyCombo::ctrl(Key::Char('z'))), Some(&Command::Undo));
+    }
+
+    #[test]
+    fn conflicts() {
+        assert!(Bindings::new(&DEFAULT_CONFIG).is_ok());
+
+        // `C-o` opens the file
+        let errors = Bindings::new(&with_button("o", None)).unwrap_err();
+        assert!(matches!(&errors[..], [Conflict { idle: false, first: Command::Open, .. }]), "{errors:?}");
+
+        let errors = Bindings::new(&with_button("x", Some("C-z"))).unwrap_err();
+        assert!(matches!(&errors[..], [Conflict { idle: false, first: Command::Undo, .. }]), "{errors:?}");
+        assert_eq!(errors[0].to_string(), "C-z is bound to both undo and button \"x\"");
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
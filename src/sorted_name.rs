@@ -0,0 +1,231 @@
+//! Sorted file names
+//!
+//! Sorted files are named `category--tag--tag__original.ext`: the category and tags typed by the
+//! user, then the name the file had before sorting. [SortedName] is the one place that knows how to
+//! read and write that format.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{fmt, str::FromStr};
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Between the category and each tag
+pub const TAG_SEPARATOR: &str = "--";
+
+/// Between the tags and the original file name
+pub const NAME_SEPARATOR: &str = "__";
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Parsed `category--tag--tag__original.ext` file name
+///
+/// Always canonical: segments are sanitized, tags are sorted and deduplicated.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SortedName {
+    category: String,
+    tags: Vec<String>,
+    original: String,
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl SortedName {
+    pub fn new<T: AsRef<str>>(
+        category: &str,
+        tags: impl IntoIterator<Item = T>,
+        original: &str,
+    ) -> Self {
+        let mut tags: Vec<String> = tags.into_iter()
+            .map(|t| sanitize_segment(t.as_ref()))
+            .filter(|t| !t.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        Self {
+            category: sanitize_segment(category),
+            tags,
+            original: sanitize_original(original),
+        }
+    }
+
+    /// Name for `original` from the `category--tag--` text typed in the filename box
+    pub fn from_typed(typed: &str, original: &str) -> Self {
+        let mut segments = typed.split(TAG_SEPARATOR);
+        let category = segments.next().unwrap_or_default();
+
+        Self::new(category, segments, original)
+    }
+
+    /// Empty when the file was sorted without one
+    pub fn category(&self) -> &str {
+        &self.category
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// File name before sorting, with whitespace replaced
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// Whether the name has `tag`, either as a tag or as its category
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.category == tag || self.tags.iter().any(|t| t == tag)
+    }
+}
+
+impl FromStr for SortedName {
+    type Err = String;
+
+    /// Parse a sorted file name, non canonical names are accepted and normalized
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // segments never contain the name separator, so the first one ends them
+        let (typed, original) = s.split_once(NAME_SEPARATOR)
+            .ok_or_else(|| format!("{s:?} is missing the {NAME_SEPARATOR:?} separator"))?;
+
+        let name = Self::from_typed(typed, original);
+        if name.original.is_empty() {
+            return Err(format!("{s:?} has no original file name"));
+        }
+
+        Ok(name)
+    }
+}
+
+impl fmt::Display for SortedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.category)?;
+        for tag in &self.tags {
+            write!(f, "{TAG_SEPARATOR}{tag}")?;
+        }
+        write!(f, "{NAME_SEPARATOR}{}", self.original)
+    }
+}
+
+/// Clean up a category or tag so it can't be confused with the separators
+///
+/// Whitespace becomes `_`, path separators and control characters are dropped, runs of `-` or `_`
+/// are collapsed and stripped from both ends.
+pub fn sanitize_segment(segment: &str) -> String {
+    let mut res = String::with_capacity(segment.len());
+
+    for c in segment.chars() {
+        let c = match c {
+            c if c.is_whitespace() => '_',
+            '/' | '\\' => continue,
+            c if c.is_control() => continue,
+            c => c,
+        };
+
+        if matches!(c, '-' | '_') && res.ends_with(c) {
+            continue;
+        }
+        res.push(c);
+    }
+
+    res.trim_matches(['-', '_']).to_string()
+}
+
+// -- private items
+/// Original names are kept as they are, except for whitespace and characters invalid in a path
+///
+/// Characters Windows doesn't allow and the replacement character left by a lossy conversion from
+/// an invalid name become `_`, so the sorted file can be copied anywhere.
+fn sanitize_original(original: &str) -> String {
+    original.chars()
+        .filter(|c| !matches!(c, '/' | '\\') && !c.is_control())
+        .map(|c| match c {
+            c if c.is_whitespace() => '_',
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' | char::REPLACEMENT_CHARACTER => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parses_and_sorts_tags() {
+        let name: SortedName = "anime--sticker--ai--ai__my picture.png".parse().unwrap();
+
+        assert_eq!(name.category(), "anime");
+        assert_eq!(name.tags(), ["ai", "sticker"]);
+        assert_eq!(name.original(), "my_picture.png");
+        assert_eq!(name.to_string(), "anime--ai--sticker__my_picture.png");
+    }
+
+    #[test]
+    fn original_may_contain_separators() {
+        let name: SortedName = "meme__a__b--c.jpg".parse().unwrap();
+
+        assert_eq!(name.category(), "meme");
+        assert!(name.tags().is_empty());
+        assert_eq!(name.original(), "a__b--c.jpg");
+    }
+
+    #[test]
+    fn typed_trailing_separator() {
+        let name = SortedName::from_typed("meme--cat--", "x.png");
+        assert_eq!(name.to_string(), "meme--cat__x.png");
+
+        let name = SortedName::from_typed("", "x.png");
+        assert_eq!(name.to_string(), "__x.png");
+    }
+
+    #[test]
+    fn unusual_originals_are_portable() {
+        let name = SortedName::from_typed("meme", "caf\u{FFFD} a:b?.png");
+        assert_eq!(name.original(), "caf__a_b_.png");
+    }
+
+    #[test]
+    fn rejects_unsorted_names() {
+        assert!("image.png".parse::<SortedName>().is_err());
+        assert!("cat--tag__".parse::<SortedName>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips(
+            category in "[a-z_ -]{0,8}",
+            tags in prop::collection::vec("[a-zA-Z0-9 _-]{0,8}", 0..5),
+            original in "[a-zA-Z0-9 ._-]{1,12}",
+        ) {
+            let name = SortedName::new(&category, &tags, &original);
+            let parsed: SortedName = name.to_string().parse().unwrap();
+
+            prop_assert_eq!(parsed, name);
+        }
+
+        #[test]
+        fn parsing_is_canonical(s in "\\PC{0,40}") {
+            if let Ok(name) = s.parse::<SortedName>() {
+                let serialized = name.to_string();
+                let reparsed: SortedName = serialized.parse().unwrap();
+
+                prop_assert_eq!(reparsed.to_string(), serialized);
+                prop_assert_eq!(reparsed, name);
+            }
+        }
+
+        #[test]
+        fn segments_have_no_separators(segment in "\\PC{0,20}") {
+            let clean = sanitize_segment(&segment);
+
+            prop_assert!(!clean.contains(TAG_SEPARATOR));
+            prop_assert!(!clean.contains(NAME_SEPARATOR));
+            prop_assert!(!clean.starts_with(['-', '_']) && !clean.ends_with(['-', '_']));
+        }
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
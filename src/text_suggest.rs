@@ -1,44 +1,238 @@
 //! Suggestion engine
+//!
+//! The first segment of a filename is completed from the categories, the following ones from the
+//! tags (categories can be used as tags too).
 
-use std::{path::PathBuf, collections::{HashSet, HashMap}, io::prelude::*};
+use std::collections::BTreeSet;
 
-use cached::{proc_macro::cached, SizedCache};
+use cached::{Cached, SizedCache};
 
 /*--- Const --------------------------------------------------------------------------------------*/
 
-pub const SEPARATOR: &'static str = "--";
+pub use crate::sorted_name::TAG_SEPARATOR as SEPARATOR;
+
+const CACHE_SIZE: usize = 50;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// Autocompletion for the filename box
+pub struct Suggester {
+    /// Search results by dataset generation, segment kind (category or tag) and prompt
+    cache: SizedCache<(u64, bool, String), Vec<String>>,
+
+    /// Candidates being cycled through with repeated completions
+    cycle: Option<Cycle>,
+}
+
+struct Cycle {
+    /// Segments before the one being completed
+    prefix: String,
+    candidates: Vec<String>,
+    selected: usize,
+    /// Filename produced by the last completion, any other value ends the cycle
+    completed: String,
+}
+
+/// Categories and tags to search, with a generation that changes whenever their contents do
+#[derive(Clone, Copy)]
+pub struct Dataset<'d> {
+    pub categories: &'d BTreeSet<String>,
+    pub tags: &'d BTreeSet<String>,
+    pub generation: u64,
+}
 
 /*--- Impl ---------------------------------------------------------------------------------------*/
 
-/// Get search results
-///
-/// Cached
-#[cached(
-    type = "SizedCache<String, Vec<String>>",
-    create = "{ SizedCache::with_size(50) }",
-    convert = "{ prompt.to_string() }"
-)]
-pub fn hashset_search(dataset: &HashSet<String>, prompt: &str) -> Vec<String> {
-    use rust_fuzzy_search::fuzzy_compare;
+impl Default for Suggester {
+    fn default() -> Self {
+        Self {
+            cache: SizedCache::with_size(CACHE_SIZE),
+            cycle: None,
+        }
+    }
+}
+
+impl Suggester {
+    /// Suggestions for the segment being typed at the end of `filename`
+    ///
+    /// While cycling through completions these are the candidates of the cycle, in order
+    pub fn suggestions(&mut self, dataset: Dataset, filename: &str) -> Vec<String> {
+        if let Some(cycle) = self.active_cycle(filename) {
+            return cycle.candidates.clone();
+        }
+
+        // a trailing separator means the last segment is done
+        if filename.ends_with(SEPARATOR) {
+            return vec![];
+        }
+
+        let segments = get_segments(filename);
+        let Some(prompt) = segments.last() else { return vec![] };
+
+        self.search(dataset, segments.len() == 1, prompt)
+    }
+
+    /// Currently selected candidate while cycling
+    pub fn selected(&self, filename: &str) -> Option<&str> {
+        self.active_cycle(filename).map(|c| c.candidates[c.selected].as_str())
+    }
+
+    /// Complete the last segment of `filename`
+    ///
+    /// Calling it again on the result moves to the next candidate. Returns the new filename, or
+    /// `None` if there is nothing to complete.
+    pub fn complete(&mut self, dataset: Dataset, filename: &str) -> Option<String> {
+        if let Some(cycle) = self.cycle.as_mut().filter(|c| c.completed == filename) {
+            cycle.selected = (cycle.selected + 1) % cycle.candidates.len();
+        } else {
+            let candidates = self.suggestions(dataset, filename);
+            if candidates.is_empty() {
+                return None;
+            }
+
+            let segments = get_segments(filename);
+            let prefix = segments[..segments.len() - 1].iter()
+                .fold(String::new(), |acc, s| acc + s + SEPARATOR);
+
+            self.cycle = Some(Cycle { prefix, candidates, selected: 0, completed: String::new() });
+        }
+
+        let cycle = self.cycle.as_mut()?;
+        cycle.completed = format!("{}{}{SEPARATOR}", cycle.prefix, cycle.candidates[cycle.selected]);
+
+        Some(cycle.completed.clone())
+    }
+
+    // -- private items
+    fn active_cycle(&self, filename: &str) -> Option<&Cycle> {
+        self.cycle.as_ref().filter(|c| c.completed == filename)
+    }
+
+    fn search(&mut self, dataset: Dataset, category: bool, prompt: &str) -> Vec<String> {
+        let key = (dataset.generation, category, prompt.to_string());
+
+        if let Some(res) = self.cache.cache_get(&key) {
+            return res.clone();
+        }
+
+        let res = if category {
+            hashset_search(dataset.categories.iter(), prompt)
+        } else {
+            hashset_search(dataset.tags.iter().chain(dataset.categories), prompt)
+        };
+
+        self.cache.cache_set(key, res.clone());
+        res
+    }
+}
+
+/// Get search results, best matches first
+pub fn hashset_search<'d>(dataset: impl Iterator<Item = &'d String>, prompt: &str) -> Vec<String> {
     use rust_fuzzy_search::fuzzy_search_sorted;
 
-    fuzzy_search_sorted(
-        prompt,
-        &dataset
-            .iter()
-            .map(|item| item.as_str())
-            .collect::<Vec<&str>>()
-    )
+    // sorted and deduplicated so ties always come out in the same order
+    let items: BTreeSet<&str> = dataset.map(String::as_str).collect();
+
+    fuzzy_search_sorted(prompt, &items.into_iter().collect::<Vec<&str>>())
         .iter()
+        .filter(|(_item, score)| *score > 0.0)
         .map(|(item, _score)| String::from(*item))
         .collect()
 }
 
-pub fn get_segments<'s>(filename: &'s str) -> Vec<&'s str> {
+pub fn get_segments(filename: &str) -> Vec<&str> {
     filename
         .split(SEPARATOR)
-        .filter_map(|s| if ! s.is_empty() { Some(s) } else { None})
+        .filter(|s| !s.is_empty())
         .collect()
 }
 
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::data_store::DataStore;
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn tab_cycles_through_the_candidates() {
+        let (categories, tags) = (set(&["anime", "animals"]), set(&["evangelion"]));
+        let dataset = Dataset { categories: &categories, tags: &tags, generation: 0 };
+        let mut suggester = Suggester::default();
+
+        let candidates = suggester.suggestions(dataset, "ani");
+        assert_eq!(candidates.len(), 2);
+
+        let first = suggester.complete(dataset, "ani").unwrap();
+        assert_eq!(first, format!("{}{SEPARATOR}", candidates[0]));
+        assert_eq!(suggester.selected(&first), Some(candidates[0].as_str()));
+        assert_eq!(suggester.suggestions(dataset, &first), candidates, "the cycle keeps its candidates");
+
+        let second = suggester.complete(dataset, &first).unwrap();
+        assert_eq!(second, format!("{}{SEPARATOR}", candidates[1]));
+        assert_eq!(suggester.complete(dataset, &second), Some(first), "wraps around");
+
+        assert_eq!(suggester.complete(dataset, "zzz"), None);
+    }
+
+    #[test]
+    fn segments_after_the_first_are_tags() {
+        let (categories, tags) = (set(&["anime"]), set(&["evangelion"]));
+        let dataset = Dataset { categories: &categories, tags: &tags, generation: 0 };
+        let mut suggester = Suggester::default();
+
+        assert_eq!(suggester.suggestions(dataset, "eva"), Vec::<String>::new(), "categories only");
+        assert_eq!(suggester.suggestions(dataset, &format!("anime{SEPARATOR}eva")), ["evangelion"]);
+        assert_eq!(suggester.suggestions(dataset, &format!("anime{SEPARATOR}")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn editing_the_prefix_ends_the_cycle() {
+        let (categories, tags) = (set(&["anime", "meme"]), set(&["evangelion", "eva-01"]));
+        let dataset = Dataset { categories: &categories, tags: &tags, generation: 0 };
+        let mut suggester = Suggester::default();
+
+        let completed = suggester.complete(dataset, &format!("anime{SEPARATOR}eva")).unwrap();
+        assert!(completed.starts_with(&format!("anime{SEPARATOR}")));
+
+        // same tag being completed, after another category
+        let edited = completed.replacen("anime", "meme", 1);
+        assert_eq!(suggester.selected(&edited), None);
+
+        let restarted = suggester.complete(dataset, &format!("meme{SEPARATOR}eva")).unwrap();
+        assert!(restarted.starts_with(&format!("meme{SEPARATOR}")), "{restarted}");
+    }
+
+    #[test]
+    fn results_are_cached_until_the_generation_changes() {
+        let mut categories = DataStore::new_from_data(Path::new("categories.yaml"), set(&["anime"]));
+        let tags = set(&[]);
+        let mut suggester = Suggester::default();
+
+        let dataset = Dataset { categories: &categories, tags: &tags, generation: categories.generation() };
+        assert_eq!(suggester.suggestions(dataset, "ani"), ["anime"]);
+
+        // reading the store keeps its generation, so the cached result is reused
+        let generation = categories.generation();
+        assert!(categories.contains("anime"));
+        assert_eq!(categories.generation(), generation);
+
+        // any mutable access starts a new one, even if nothing ends up changing
+        categories.insert("animals".to_string());
+        assert_ne!(categories.generation(), generation);
+
+        let stale = Dataset { categories: &categories, tags: &tags, generation };
+        assert_eq!(suggester.suggestions(stale, "ani"), ["anime"], "same generation, cached result");
+
+        let dataset = Dataset { categories: &categories, tags: &tags, generation: categories.generation() };
+        assert_eq!(suggester.suggestions(dataset, "ani").len(), 2);
+    }
+}
+
 /*--------------------------------------------- EOF ----------------------------------------------*/
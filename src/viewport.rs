@@ -0,0 +1,218 @@
+//! Zoom and pan of the shown image
+//!
+//! Only the geometry, sizes are in screen pixels unless noted. The offset is the image point shown
+//! at the center of the canvas, in image pixels from the image center with y pointing down, so it
+//! stays on the same detail when the zoom or the window size change.
+
+/*--- Const --------------------------------------------------------------------------------------*/
+
+/// Range of the free zoom factor
+pub const MIN_ZOOM: f32 = 0.05;
+pub const MAX_ZOOM: f32 = 16.0;
+
+/*--- Types --------------------------------------------------------------------------------------*/
+
+/// How the image is scaled to the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Zoom {
+    /// Whole image visible
+    #[default]
+    Fit,
+    /// Canvas width, tall images scroll vertically
+    FillWidth,
+    /// One image pixel per screen pixel
+    Actual,
+    /// Screen pixels per image pixel
+    Free(f32),
+}
+
+/// Zoom and pan of one image
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Viewport {
+    pub zoom: Zoom,
+    /// Image point at the center of the canvas, see the module docs
+    pub offset: [f32; 2],
+}
+
+/*--- Impl ---------------------------------------------------------------------------------------*/
+
+impl Zoom {
+    /// Screen pixels per image pixel for an `image` sized image on a `canvas` sized area
+    pub fn factor(self, image: [f32; 2], canvas: [f32; 2]) -> f32 {
+        let ([iw, ih], [cw, ch]) = (image, canvas);
+        if iw <= 0.0 || ih <= 0.0 {
+            return 1.0;
+        }
+
+        match self {
+            Self::Fit => (cw / iw).min(ch / ih),
+            Self::FillWidth => cw / iw,
+            Self::Actual => 1.0,
+            Self::Free(factor) => factor,
+        }
+    }
+}
+
+impl Viewport {
+    /// Drawn size of the image and position of its center, relative to the canvas center with y
+    /// pointing down
+    pub fn layout(&self, image: [f32; 2], canvas: [f32; 2]) -> ([f32; 2], [f32; 2]) {
+        let factor = self.zoom.factor(image, canvas);
+        let [x, y] = self.clamped(self.offset, image, canvas);
+
+        ([image[0] * factor, image[1] * factor], [-x * factor, -y * factor])
+    }
+
+    /// Switch to `zoom`, filling the width starts at the top of the image
+    pub fn set_zoom(&mut self, zoom: Zoom, image: [f32; 2], canvas: [f32; 2]) {
+        self.zoom = zoom;
+        if zoom == Zoom::FillWidth {
+            self.offset = [0.0, f32::NEG_INFINITY];
+        }
+        self.offset = self.clamped(self.offset, image, canvas);
+    }
+
+    /// Multiply the zoom by `factor`, keeping the image point under `anchor` in place
+    ///
+    /// `anchor` is relative to the canvas center with y pointing down.
+    pub fn zoom_by(&mut self, factor: f32, anchor: [f32; 2], image: [f32; 2], canvas: [f32; 2]) {
+        let old = self.zoom.factor(image, canvas);
+        if old <= 0.0 {
+            return;
+        }
+        let new = (old * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+
+        let offset = self.clamped(self.offset, image, canvas);
+        self.zoom = Zoom::Free(new);
+        self.offset = self.clamped(
+            [0, 1].map(|i| offset[i] + anchor[i] / old - anchor[i] / new),
+            image,
+            canvas,
+        );
+    }
+
+    /// Move the view by `delta` screen pixels, positive values show more of the bottom right
+    pub fn pan(&mut self, delta: [f32; 2], image: [f32; 2], canvas: [f32; 2]) {
+        let factor = self.zoom.factor(image, canvas);
+        if factor <= 0.0 {
+            return;
+        }
+
+        let offset = self.clamped(self.offset, image, canvas);
+        self.offset = self.clamped([0, 1].map(|i| offset[i] + delta[i] / factor), image, canvas);
+    }
+
+    // -- private items
+    /// `offset` limited so the image never leaves more of the canvas empty than it has to
+    fn clamped(&self, offset: [f32; 2], image: [f32; 2], canvas: [f32; 2]) -> [f32; 2] {
+        let factor = self.zoom.factor(image, canvas);
+
+        [0, 1].map(|i| {
+            let max = ((image[i] - canvas[i] / factor) / 2.0).max(0.0);
+            offset[i].clamp(-max, max)
+        })
+    }
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twice as tall as wide, on a canvas with the opposite shape
+    const TALL: [f32; 2] = [100.0, 400.0];
+    const CANVAS: [f32; 2] = [200.0, 100.0];
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3
+    }
+
+    #[test]
+    fn factors() {
+        assert_eq!(Zoom::Fit.factor(TALL, CANVAS), 0.25);
+        assert_eq!(Zoom::FillWidth.factor(TALL, CANVAS), 2.0);
+        assert_eq!(Zoom::Actual.factor(TALL, CANVAS), 1.0);
+        assert_eq!(Zoom::Free(3.0).factor(TALL, CANVAS), 3.0);
+
+        // small images are enlarged to fit as well
+        assert_eq!(Zoom::Fit.factor([10.0, 10.0], CANVAS), 10.0);
+    }
+
+    #[test]
+    fn offset_is_clamped_to_the_image() {
+        let mut view = Viewport { zoom: Zoom::Actual, offset: [1000.0, -1000.0] };
+
+        // wider than the image, nothing to pan horizontally, 150 px of the height are hidden
+        let ([w, h], center) = view.layout(TALL, CANVAS);
+        assert_eq!([w, h], TALL);
+        assert!(close(center, [0.0, 150.0]));
+
+        view.pan([0.0, 1000.0], TALL, CANVAS);
+        assert!(close(view.offset, [0.0, 150.0]));
+
+        // a fitted image can't be panned at all
+        view.set_zoom(Zoom::Fit, TALL, CANVAS);
+        assert!(close(view.offset, [0.0, 0.0]));
+        view.pan([50.0, 50.0], TALL, CANVAS);
+        assert!(close(view.offset, [0.0, 0.0]));
+    }
+
+    #[test]
+    fn fill_width_starts_at_the_top() {
+        let mut view = Viewport::default();
+        view.set_zoom(Zoom::FillWidth, TALL, CANVAS);
+
+        // 800 px tall on a 100 px canvas, the top edge at the top of the canvas
+        let ([_, h], [_, y]) = view.layout(TALL, CANVAS);
+        assert_eq!(h, 800.0);
+        assert!((y - h / 2.0 + CANVAS[1] / 2.0).abs() < 1e-3);
+
+        view.pan([0.0, 10_000.0], TALL, CANVAS);
+        let ([_, h], [_, y]) = view.layout(TALL, CANVAS);
+        assert!((y + h / 2.0 - CANVAS[1] / 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zoom_keeps_the_anchor_in_place() {
+        // large enough to pan both ways at any of these zooms
+        const BIG: [f32; 2] = [1000.0, 1000.0];
+        let mut view = Viewport { zoom: Zoom::Actual, offset: [0.0, 0.0] };
+        let anchor = [30.0, -20.0];
+        // image point under the anchor, relative to the image center
+        let point = |v: &Viewport| {
+            let factor = v.zoom.factor(BIG, CANVAS);
+            let (_, [x, y]) = v.layout(BIG, CANVAS);
+            [(anchor[0] - x) / factor, (anchor[1] - y) / factor]
+        };
+
+        let before = point(&view);
+        view.zoom_by(2.0, anchor, BIG, CANVAS);
+        assert_eq!(view.zoom, Zoom::Free(2.0));
+        assert!(close(point(&view), before));
+
+        view.zoom_by(1000.0, anchor, BIG, CANVAS);
+        assert_eq!(view.zoom, Zoom::Free(MAX_ZOOM));
+        view.zoom_by(0.0, anchor, BIG, CANVAS);
+        assert_eq!(view.zoom, Zoom::Free(MIN_ZOOM));
+    }
+
+    #[test]
+    fn zero_sized_canvas_or_image() {
+        let mut view = Viewport::default();
+
+        // nothing to show, but nothing breaks either
+        view.zoom_by(2.0, [0.0, 0.0], TALL, [0.0, 0.0]);
+        view.pan([10.0, 10.0], TALL, [0.0, 0.0]);
+        assert_eq!(view, Viewport::default());
+
+        let ([w, h], [x, y]) = view.layout(TALL, [0.0, 0.0]);
+        assert!([w, h, x, y].iter().all(|v| v.is_finite()));
+
+        assert_eq!(Zoom::Fit.factor([0.0, 0.0], CANVAS), 1.0);
+        view.set_zoom(Zoom::FillWidth, [0.0, 0.0], CANVAS);
+        assert!(view.offset.iter().all(|v| v.is_finite()));
+    }
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
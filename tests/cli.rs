@@ -0,0 +1,122 @@
+//! Headless subcommand integration tests
+//!
+//! Every test runs a subcommand on a temporary folder and checks what it left on disk.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{fs, path::Path, process::ExitCode};
+
+use image_sorter::{
+    cli::{self, CliCommand},
+    config::{CollisionPolicy, DEFAULT_CONFIG, DEFAULT_CONFIG_S},
+    index::INDEX_FILE_NAME,
+    SortQueue,
+};
+use tempfile::TempDir;
+
+/*--- Helpers ------------------------------------------------------------------------------------*/
+
+/// Folder with `a.png` sorted into `output` and `b.png` left to sort, without a config file
+fn sorted_folder() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.png"), b"\x89PNG\r\n\x1a\na").unwrap();
+    fs::write(dir.path().join("b.png"), b"\x89PNG\r\n\x1a\nb").unwrap();
+
+    let mut queue = SortQueue::new(dir.path(), &DEFAULT_CONFIG).unwrap();
+    queue.create_output_dirs(&DEFAULT_CONFIG).unwrap();
+    queue.move_current("output", "anime--sticker", CollisionPolicy::Suffix).unwrap();
+
+    dir
+}
+
+/// Files the sorter keeps in `dir`, by name
+fn own_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(".image-sorter"))
+        .collect();
+    names.sort();
+    names
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[test]
+fn search_and_stats_write_nothing() {
+    let dir = sorted_folder();
+    let before = own_files(dir.path());
+
+    let search = CliCommand::Search { query: vec!["anime".into(), "not".into(), "meme".into()] };
+    assert_eq!(cli::run(dir.path(), search), ExitCode::SUCCESS);
+    assert_eq!(cli::run(dir.path(), CliCommand::Stats), ExitCode::SUCCESS);
+
+    assert_eq!(own_files(dir.path()), before);
+    assert!(!dir.path().join(".image-sorter.yaml").exists());
+    assert!(!dir.path().join(format!("{INDEX_FILE_NAME}.bak")).exists());
+}
+
+#[test]
+fn invalid_query_fails() {
+    let dir = sorted_folder();
+    let search = CliCommand::Search { query: vec!["(anime".into()] };
+
+    assert_eq!(cli::run(dir.path(), search), ExitCode::FAILURE);
+}
+
+#[test]
+fn sort_fails_headless() {
+    let dir = sorted_folder();
+
+    assert_eq!(cli::run(dir.path(), CliCommand::Sort), ExitCode::FAILURE);
+}
+
+#[test]
+fn reindex_saves_the_index() {
+    let dir = sorted_folder();
+    fs::remove_file(dir.path().join(INDEX_FILE_NAME)).ok();
+
+    assert_eq!(cli::run(dir.path(), CliCommand::Reindex { full: true }), ExitCode::SUCCESS);
+    let index = fs::read_to_string(dir.path().join(INDEX_FILE_NAME)).unwrap();
+    assert!(index.contains("anime--sticker__a.png"));
+}
+
+#[test]
+fn undo_restores_moves_from_the_window() {
+    let dir = sorted_folder();
+
+    assert_eq!(cli::run(dir.path(), CliCommand::Undo { count: 5 }), ExitCode::SUCCESS);
+    assert!(dir.path().join("a.png").exists());
+    assert!(!dir.path().join("output/anime--sticker__a.png").exists());
+
+    // nothing left to undo is not an error
+    assert_eq!(cli::run(dir.path(), CliCommand::Undo { count: 1 }), ExitCode::SUCCESS);
+    assert!(dir.path().join("a.png").exists());
+}
+
+#[test]
+fn dry_run_moves_nothing() {
+    let dir = sorted_folder();
+    let rule = "rules:\n  - query: \"sticker\"\n    path: \"stickers\"";
+    let config = DEFAULT_CONFIG_S.replace("# rules:\n#   - query: \"sticker\"\n#     path: \"stickers\"", rule);
+    assert!(config.contains(rule));
+    fs::write(dir.path().join(".image-sorter.yaml"), config).unwrap();
+
+    assert_eq!(cli::run(dir.path(), CliCommand::ApplyRules { dry_run: true }), ExitCode::SUCCESS);
+    assert!(dir.path().join("output/anime--sticker__a.png").exists());
+
+    assert_eq!(cli::run(dir.path(), CliCommand::ApplyRules { dry_run: false }), ExitCode::SUCCESS);
+    assert!(dir.path().join("stickers/anime--sticker__a.png").exists());
+}
+
+#[test]
+fn validate_config_creates_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+
+    assert_eq!(cli::run(dir.path(), CliCommand::ValidateConfig), ExitCode::SUCCESS);
+    assert!(own_files(dir.path()).is_empty());
+
+    fs::write(dir.path().join(".image-sorter.yaml"), "buttons: [").unwrap();
+    assert_eq!(cli::run(dir.path(), CliCommand::ValidateConfig), ExitCode::FAILURE);
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/
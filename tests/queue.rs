@@ -0,0 +1,489 @@
+//! Sorting queue integration tests
+//!
+//! Every test sorts a temporary folder and checks the resulting tree, no file may ever be lost.
+
+/*--- Use ----------------------------------------------------------------------------------------*/
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use image_sorter::{
+    config::{CollisionPolicy, Config, DEFAULT_CONFIG},
+    index::LibraryIndex,
+    journal::{Journal, Recovery, JOURNAL_FILE_NAME},
+    metadata::Metadata,
+    orientation::{Orientation, ORIGINALS_DIR},
+    queue::SkipReason,
+    Error, ImageFormat, MoveOutcome, SortQueue,
+};
+use image::GenericImageView;
+use tempfile::TempDir;
+
+/*--- Helpers ------------------------------------------------------------------------------------*/
+
+/// Temporary folder to sort with its queue
+struct Fixture {
+    dir: TempDir,
+    config: Config,
+}
+
+impl Fixture {
+    /// Folder with the given files and the output folders of the default config
+    fn new(files: &[(&str, Vec<u8>)]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+
+        Self { dir, config: DEFAULT_CONFIG.clone() }
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn queue(&self) -> SortQueue {
+        let queue = SortQueue::new(self.path(), &self.config).unwrap();
+        queue.create_output_dirs(&self.config).unwrap();
+        queue
+    }
+
+    /// Every file in the folder by relative path, leaving out the state files of the sorter
+    fn tree(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut tree = BTreeMap::new();
+        walk(self.path(), self.path(), &mut tree);
+        tree
+    }
+}
+
+fn walk(root: &Path, dir: &Path, tree: &mut BTreeMap<PathBuf, Vec<u8>>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.file_name().unwrap().to_string_lossy().starts_with(".image-sorter") {
+            continue;
+        }
+
+        if path.is_dir() {
+            walk(root, &path, tree);
+        } else {
+            tree.insert(path.strip_prefix(root).unwrap().to_path_buf(), fs::read(&path).unwrap());
+        }
+    }
+}
+
+/// Small valid PNG, `seed` changes its pixels
+fn png(seed: u8) -> Vec<u8> {
+    let img = image::RgbImage::from_fn(4, 4, |x, y| image::Rgb([seed, x as u8, y as u8]));
+    let mut buf = Cursor::new(vec![]);
+    image::DynamicImage::ImageRgb8(img).write_to(&mut buf, image::ImageOutputFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+/// 4x2 JPEG tagged to be rotated 90 degrees clockwise, taken with a "TestCam"
+fn rotated_jpeg() -> Vec<u8> {
+    let img = image::RgbImage::from_fn(4, 2, |x, _| image::Rgb([x as u8 * 60, 0, 0]));
+    let mut buf = Cursor::new(vec![]);
+    image::DynamicImage::ImageRgb8(img).write_to(&mut buf, image::ImageOutputFormat::Jpeg(90)).unwrap();
+    let jpeg = buf.into_inner();
+
+    // APP1 with a big endian TIFF header and an IFD with the make (0x10f) and orientation (0x112)
+    let app1: &[u8] = &[
+        0xff, 0xe1, 0, 54, b'E', b'x', b'i', b'f', 0, 0,
+        b'M', b'M', 0, 42, 0, 0, 0, 8,
+        0, 2,
+        0x01, 0x0f, 0, 2, 0, 0, 0, 8, 0, 0, 0, 38,
+        0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0,
+        0, 0, 0, 0,
+        b'T', b'e', b's', b't', b'C', b'a', b'm', 0,
+    ];
+    [&jpeg[..2], app1, &jpeg[2..]].concat()
+}
+
+fn tree_of(files: &[(&str, &[u8])]) -> BTreeMap<PathBuf, Vec<u8>> {
+    files.iter().map(|(p, c)| (PathBuf::from(p), c.to_vec())).collect()
+}
+
+/*--- Tests --------------------------------------------------------------------------------------*/
+
+#[test]
+fn empty_folder_is_an_empty_queue() {
+    let fx = Fixture::new(&[]);
+    let mut queue = fx.queue();
+
+    assert!(queue.is_empty());
+    assert_eq!(queue.get_current_filename(), None);
+
+    queue.next_image();
+    queue.prev_image();
+    queue.seek_to_image(3);
+    let res = queue.move_current("output", "anime", CollisionPolicy::Suffix);
+    assert!(matches!(res, Err(Error::EmptyQueue)));
+}
+
+#[test]
+fn navigation_is_clamped() {
+    let (a, b, c) = (png(1), png(2), png(3));
+    let fx = Fixture::new(&[("a.png", a), ("b.png", b), ("c.png", c), ("notes.txt", vec![1])]);
+    let mut queue = fx.queue();
+
+    assert_eq!(queue.images, ["a.png", "b.png", "c.png"]);
+
+    queue.prev_image();
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("a.png"));
+
+    queue.next_image();
+    queue.next_image();
+    queue.next_image();
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("c.png"));
+
+    queue.seek_to_image(1);
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("b.png"));
+
+    queue.seek_to_image(100);
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("c.png"));
+}
+
+#[test]
+fn formats_are_detected_from_contents() {
+    let jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 0x10];
+    let bmp = [b"BM".as_slice(), &[0; 12], &40u32.to_le_bytes()].concat();
+    let mut fx = Fixture::new(&[
+        ("a.JPG", png(1)),
+        ("b.gif", b"GIF89a\x01\x00\x01\x00".to_vec()),
+        ("bmi notes.txt", b"BMI 22.5, weighed on monday".to_vec()),
+        ("c.TIFF", b"II*\x00\x08\x00".to_vec()),
+        ("d.dat", bmp),
+        ("empty.JPEG", vec![]),
+        ("notes.png", b"not an image".to_vec()),
+        ("notes.txt", b"not an image".to_vec()),
+        ("photo.txt", jpeg),
+    ]);
+    fx.config.formats.remove(&ImageFormat::Tiff);
+
+    let queue = fx.queue();
+    assert_eq!(queue.images, ["a.JPG", "b.gif", "d.dat", "empty.JPEG", "photo.txt"]);
+
+    let skipped: Vec<_> = queue.skipped().iter().map(|s| (s.name.to_str().unwrap(), &s.reason)).collect();
+    assert!(matches!(skipped[..], [
+        ("bmi notes.txt", SkipReason::Unsupported),
+        ("c.TIFF", SkipReason::Disabled(ImageFormat::Tiff)),
+        ("notes.png", SkipReason::Unsupported),
+        ("notes.txt", SkipReason::Unsupported),
+    ]), "{skipped:?}");
+}
+
+#[test]
+fn moves_build_the_sorted_name() {
+    let (a, b) = (png(1), png(2));
+    let fx = Fixture::new(&[("a b.png", a.clone()), ("b.png", b.clone())]);
+    let mut queue = fx.queue();
+
+    let outcome = queue.move_current("output", "anime--sticker--", CollisionPolicy::Suffix).unwrap();
+    assert!(matches!(outcome, MoveOutcome::Moved(_)));
+
+    // trailing separators are dropped and tags sorted
+    queue.move_current("other", "meme--zz--aa--zz", CollisionPolicy::Suffix).unwrap();
+
+    assert!(queue.is_empty());
+    assert_eq!(queue.get_total_files(), 2);
+    assert_eq!(fx.tree(), tree_of(&[
+        ("output/anime--sticker__a_b.png", &a),
+        ("other/meme--aa--zz__b.png", &b),
+    ]));
+}
+
+#[test]
+fn suffix_collision_keeps_both() {
+    let (new, old) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", new.clone())]);
+    fs::create_dir_all(fx.path().join("output")).unwrap();
+    fs::write(fx.path().join("output/anime__a.png"), &old).unwrap();
+
+    let mut queue = fx.queue();
+    queue.move_current("output", "anime", CollisionPolicy::Suffix).unwrap();
+
+    assert_eq!(fx.tree(), tree_of(&[
+        ("output/anime__a.png", &old),
+        ("output/anime__a_1.png", &new),
+    ]));
+}
+
+#[test]
+fn skip_identical_leaves_the_file_queued() {
+    let (a, b) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", a.clone()), ("b.png", b.clone())]);
+    fs::create_dir_all(fx.path().join("output")).unwrap();
+    fs::write(fx.path().join("output/anime__a.png"), &a).unwrap();
+
+    let mut queue = fx.queue();
+    let outcome = queue.move_current("output", "anime", CollisionPolicy::SkipIdentical).unwrap();
+
+    assert!(matches!(outcome, MoveOutcome::Skipped { .. }));
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("b.png"));
+    assert_eq!(fx.tree(), tree_of(&[
+        ("a.png", &a),
+        ("b.png", &b),
+        ("output/anime__a.png", &a),
+    ]));
+}
+
+#[test]
+fn hash_collision_names_by_contents() {
+    let (new, old) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", new.clone())]);
+    fs::create_dir_all(fx.path().join("output")).unwrap();
+    fs::write(fx.path().join("output/anime__a.png"), &old).unwrap();
+
+    let mut queue = fx.queue();
+    let MoveOutcome::Moved(path) = queue.move_current("output", "anime", CollisionPolicy::Hash).unwrap() else {
+        panic!("expected a move");
+    };
+
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("anime__a_") && name.len() == "anime__a_12345678.png".len(), "{name}");
+    assert_eq!(fx.tree().len(), 2);
+}
+
+#[test]
+fn prompt_collision_changes_nothing() {
+    let (new, old) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", new.clone())]);
+    fs::create_dir_all(fx.path().join("output")).unwrap();
+    fs::write(fx.path().join("output/anime__a.png"), &old).unwrap();
+
+    let mut queue = fx.queue();
+    let outcome = queue.move_current("output", "anime", CollisionPolicy::Prompt).unwrap();
+
+    assert!(matches!(outcome, MoveOutcome::Conflict { .. }));
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("a.png"));
+    assert_eq!(fx.tree(), tree_of(&[("a.png", &new), ("output/anime__a.png", &old)]));
+}
+
+#[test]
+fn unreadable_images_are_still_sorted() {
+    // a PNG signature followed by garbage
+    let broken = b"\x89PNG\r\n\x1a\ndefinitely not a png".to_vec();
+    let fx = Fixture::new(&[("broken.png", broken.clone()), ("empty.jpg", vec![])]);
+    let mut queue = fx.queue();
+
+    queue.move_current("trash", "", CollisionPolicy::Suffix).unwrap();
+    queue.move_current("trash", "", CollisionPolicy::Suffix).unwrap();
+
+    assert_eq!(fx.tree(), tree_of(&[("trash/__broken.png", &broken), ("trash/__empty.jpg", &[])]));
+}
+
+#[test]
+fn failed_move_keeps_the_source() {
+    let a = png(1);
+    let fx = Fixture::new(&[("a.png", a.clone())]);
+    let mut queue = fx.queue();
+
+    // the destination folder doesn't exist
+    let res = queue.move_current("missing", "anime", CollisionPolicy::Suffix);
+    assert!(matches!(res, Err(Error::Move { .. })), "{res:?}");
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("a.png"));
+    assert_eq!(fx.tree(), tree_of(&[("a.png", &a)]));
+}
+
+#[test]
+fn undo_and_redo_restore_the_tree() {
+    let (a, b) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", a.clone()), ("b.png", b.clone())]);
+    let mut queue = fx.queue();
+
+    queue.move_current("output", "anime--", CollisionPolicy::Suffix).unwrap();
+    let sorted = fx.tree();
+
+    assert_eq!(queue.undo().unwrap().as_deref(), Some("anime--"));
+    assert_eq!(queue.images, ["a.png", "b.png"]);
+    assert_eq!(fx.tree(), tree_of(&[("a.png", &a), ("b.png", &b)]));
+
+    queue.redo().unwrap();
+    assert_eq!(fx.tree(), sorted);
+
+    // undoable from the journal as well, like the `undo` subcommand does
+    let undoable = Journal::undoable(queue.dir()).unwrap();
+    assert_eq!(undoable.len(), 1);
+    assert_eq!(undoable[0].source, queue.dir().join("a.png"));
+}
+
+#[test]
+fn index_follows_moves_during_a_scan() {
+    let (a, b, c) = (png(1), png(2), png(3));
+    let fx = Fixture::new(&[("a.png", a), ("b.png", b)]);
+    fs::create_dir(fx.path().join("output")).unwrap();
+    fs::write(fx.path().join("output/old.png"), c).unwrap();
+
+    let mut queue = fx.queue();
+    queue.reindex(&fx.config);
+    queue.move_current("output", "anime--", CollisionPolicy::Suffix).unwrap();
+
+    for _ in 0..500 {
+        if queue.poll_reindex() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let indexed = |index: &LibraryIndex| {
+        index.entries().map(|e| e.path.clone()).collect::<Vec<_>>()
+    };
+    let expected = [PathBuf::from("output/anime__a.png"), PathBuf::from("output/old.png")];
+    assert_eq!(indexed(queue.index()), expected);
+
+    // moves since the last save are written when the queue is dropped
+    queue.move_current("output", "meme--", CollisionPolicy::Suffix).unwrap();
+    drop(queue);
+    let saved = LibraryIndex::open(fx.path()).unwrap();
+    assert_eq!(indexed(&saved).len(), 3);
+}
+
+#[test]
+fn interrupted_move_is_completed() {
+    let a = png(1);
+    let fx = Fixture::new(&[("a.png", a.clone())]);
+    let dir = fx.path().canonicalize().unwrap();
+
+    // crash after the copy was verified but before the source was removed
+    fs::create_dir_all(dir.join("output")).unwrap();
+    fs::write(dir.join("output/anime__a.png"), &a).unwrap();
+    let begin = serde_json::json!({
+        "op": "begin",
+        "id": 0,
+        "source": dir.join("a.png"),
+        "destination": dir.join("output/anime__a.png"),
+    });
+    fs::write(dir.join(JOURNAL_FILE_NAME), format!("{begin}\n")).unwrap();
+
+    let queue = fx.queue();
+
+    assert!(queue.is_empty());
+    assert_eq!(fx.tree(), tree_of(&[("output/anime__a.png", &a)]));
+}
+
+#[test]
+fn interrupted_move_to_a_different_file_is_rolled_back() {
+    let (a, b) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", a.clone())]);
+    let dir = fx.path().canonicalize().unwrap();
+
+    // the destination isn't a copy of the source, neither may be deleted
+    fs::create_dir_all(dir.join("output")).unwrap();
+    fs::write(dir.join("output/anime__a.png"), &b).unwrap();
+    let begin = serde_json::json!({
+        "op": "begin",
+        "id": 0,
+        "source": dir.join("a.png"),
+        "destination": dir.join("output/anime__a.png"),
+    });
+    fs::write(dir.join(JOURNAL_FILE_NAME), format!("{begin}\n")).unwrap();
+
+    let recovered = Journal::recover(&dir).unwrap();
+    assert!(matches!(recovered[..], [Recovery::Differs { .. }]), "{recovered:?}");
+
+    let queue = fx.queue();
+    assert_eq!(queue.images, ["a.png"]);
+    assert_eq!(fx.tree(), tree_of(&[("a.png", &a), ("output/anime__a.png", &b)]));
+    assert!(Journal::undoable(&dir).unwrap().is_empty());
+}
+
+#[test]
+fn journal_is_compacted_on_recovery() {
+    let (a, b) = (png(1), png(2));
+    let fx = Fixture::new(&[("a.png", a), ("b.png", b)]);
+    let mut queue = fx.queue();
+
+    queue.move_current("output", "anime--", CollisionPolicy::Suffix).unwrap();
+    queue.move_current("output", "meme--", CollisionPolicy::Suffix).unwrap();
+    queue.undo().unwrap();
+    drop(queue);
+
+    let journal = fx.path().join(JOURNAL_FILE_NAME);
+    let lines = || fs::read_to_string(&journal).unwrap().lines().count();
+    assert_eq!(lines(), 6);
+
+    // the undone move and its undo are dropped, the newest one is kept for its id
+    assert!(Journal::recover(fx.path()).unwrap().is_empty());
+    assert_eq!(lines(), 4);
+
+    let undoable = Journal::undoable(fx.path()).unwrap();
+    assert_eq!(undoable.len(), 1);
+    assert_eq!(undoable[0].source, fx.path().canonicalize().unwrap().join("a.png"));
+
+    let next = Journal::open(fx.path()).unwrap().begin(Path::new("x"), Path::new("y"), None).unwrap();
+    assert_eq!(next, 3);
+}
+
+#[test]
+fn missing_folder_is_an_error() {
+    let fx = Fixture::new(&[]);
+    let res = SortQueue::new(&fx.path().join("missing"), &fx.config);
+
+    assert!(matches!(res, Err(Error::OpenFolder(..))));
+}
+
+#[test]
+fn orientation_is_baked_on_move() {
+    let jpeg = rotated_jpeg();
+    let fx = Fixture::new(&[("a.jpg", jpeg.clone()), ("b.jpg", jpeg.clone())]);
+    assert_eq!(Orientation::read(&fx.path().join("a.jpg")), Orientation::Rotate90);
+
+    // left alone by default
+    let mut queue = fx.queue();
+    queue.move_current("output", "kept", CollisionPolicy::Suffix).unwrap();
+    assert_eq!(fs::read(fx.path().join("output/kept__a.jpg")).unwrap(), jpeg);
+
+    queue.set_bake_orientation(true);
+    let outcome = queue.move_current("output", "baked", CollisionPolicy::Suffix).unwrap();
+    let MoveOutcome::Moved(path) = outcome else { panic!("not moved: {outcome:?}") };
+
+    // only the orientation tag changes
+    assert_eq!(image::open(&path).unwrap().dimensions(), (2, 4));
+    assert_eq!(Orientation::read(&path), Orientation::Normal);
+    assert_eq!(Metadata::read(&path).unwrap().camera.as_deref(), Some("TestCam"));
+    assert_eq!(fx.tree().len(), 2);
+
+    // undo puts the original bytes back
+    queue.undo().unwrap();
+    assert_eq!(fs::read(fx.path().join("b.jpg")).unwrap(), jpeg);
+    assert!(fs::read_dir(fx.path().join(ORIGINALS_DIR)).unwrap().next().is_none());
+
+    // and redo rotates it again
+    queue.redo().unwrap();
+    assert_eq!(image::open(&path).unwrap().dimensions(), (2, 4));
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names_are_sorted() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let a = png(1);
+    let fx = Fixture::new(&[]);
+    // latin-1 "café photo.png", as found on old Windows archives
+    let name = OsStr::from_bytes(b"caf\xe9 photo.png");
+    fs::write(fx.path().join(name), &a).unwrap();
+
+    let mut queue = fx.queue();
+    assert_eq!(queue.images, [name]);
+    assert_eq!(queue.get_current_display_name().as_deref(), Some("caf\u{FFFD} photo.png"));
+
+    queue.move_current("output", "food", CollisionPolicy::Suffix).unwrap();
+    assert_eq!(fx.tree(), tree_of(&[("output/food__caf__photo.png", &a)]));
+
+    // the journal keeps the original name so the move can be undone
+    queue.undo().unwrap();
+    assert_eq!(queue.images, [name]);
+    assert_eq!(fs::read(fx.path().join(name)).unwrap(), a);
+
+    let undoable = Journal::undoable(queue.dir()).unwrap();
+    assert!(undoable.is_empty());
+}
+
+/*--------------------------------------------- EOF ----------------------------------------------*/